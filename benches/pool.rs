@@ -0,0 +1,81 @@
+use rust_mpc::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+// Counting Allocator
+
+struct Counter;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counter {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counter = Counter;
+
+// Grammars
+
+// Words and numbers, most values are strings with buffers of their own.
+fn tokens() -> Parser<usize> {
+    let num = mpc_apply(&mpc_tok(&mpc_int()), |x| x as i64);
+    let word = mpc_apply(&mpc_tok(&mpc_many1(mpcf_strfold, &mpc_alpha())), |x| {
+        x.len() as i64
+    });
+    let item = mpc_or(&[&num, &word]);
+    mpc_many(|xs: Vec<i64>| xs.len(), &item)
+}
+
+// Comma separated digits folded straight into integers.
+fn numbers() -> Parser<usize> {
+    let digit = mpc_apply(&mpc_range('0', '9'), |x| x.as_bytes()[0] - b'0');
+    let num = mpc_many1(
+        |xs: Vec<u8>| xs.iter().fold(0u64, |n, d| n * 10 + *d as u64),
+        &mpc_apply(&digit, |d| d),
+    );
+    let item = mpc_and2(|n, _| n, &num, &mpc_maybe(&mpc_char(',')));
+    mpc_many(|xs: Vec<u64>| xs.len(), &item)
+}
+
+fn run(p: &Parser<usize>, src: &str, pool: bool) -> (usize, usize, u128) {
+    let before = ALLOCS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut i = mpc_input_new_string("<bench>", src);
+    if !pool {
+        mpc_input_pool_disable(&mut i);
+    }
+    let n = mpc_parse_input(&mut i, p).expect("parse failed");
+    let elapsed = start.elapsed().as_micros();
+    (n, ALLOCS.load(Ordering::Relaxed) - before, elapsed)
+}
+
+fn bench(name: &str, p: &Parser<usize>, src: &str) {
+    let (n, heap, heap_us) = run(p, src, false);
+    let (m, pooled, pooled_us) = run(p, src, true);
+    assert_eq!(n, m);
+
+    println!("{name} ({n} items)");
+    println!("  allocations heap:  {heap:>8} ({heap_us} us)");
+    println!("  allocations pool:  {pooled:>8} ({pooled_us} us)");
+    println!(
+        "  reduction:         {:>7.1}%",
+        100.0 * (1.0 - pooled as f64 / heap as f64)
+    );
+}
+
+fn main() {
+    bench(
+        "tokens",
+        &tokens(),
+        &"foo 123 bar 4567 baz 89 ".repeat(2000),
+    );
+    bench("numbers", &numbers(), &"123,4567,89,".repeat(2000));
+}
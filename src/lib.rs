@@ -1,21 +1,33 @@
 #![allow(non_camel_case_types)]
-use std::default;
+use std::any::TypeId;
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Arguments;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::os::raw::*;
-
-#[derive(Debug, Clone, Copy, Default)]
-struct mpc_state_t {
-    pos: i32,
-    row: i32,
-    col: i32,
-    term: i32,
+use std::io::{BufReader, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::ops::Deref;
+use std::ptr;
+use std::rc::Rc;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+// `pos` is a byte offset into the input while `col` counts characters, or
+// whatever else the input's column mode says. While parsing only `pos` is kept
+// up to date, `row` and `col` are looked up in the line index for states
+// handed out of the parser.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct mpc_state_t {
+    pub pos: i32,
+    pub row: i32,
+    pub col: i32,
+    pub term: i32,
 }
 
 // State Types
 
-fn mpc_state_invalid() -> mpc_state_t {
+pub fn mpc_state_invalid() -> mpc_state_t {
     mpc_state_t {
         pos: -1,
         row: -1,
@@ -24,7 +36,7 @@ fn mpc_state_invalid() -> mpc_state_t {
     }
 }
 
-fn mpc_state_new() -> mpc_state_t {
+pub fn mpc_state_new() -> mpc_state_t {
     mpc_state_t {
         pos: 0,
         row: 0,
@@ -33,6 +45,22 @@ fn mpc_state_new() -> mpc_state_t {
     }
 }
 
+// A stretch of matched input, `end` being the state just past its last character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct mpc_span_t {
+    pub start: mpc_state_t,
+    pub end: mpc_state_t,
+}
+
+// Borrows the text a span covers from the string it was parsed from.
+pub fn mpc_span_str<'a>(s: &'a str, x: &mpc_span_t) -> &'a str {
+    &s[x.start.pos as usize..x.end.pos as usize]
+}
+
+pub fn mpc_span_bytes<'a>(b: &'a [u8], x: &mpc_span_t) -> &'a [u8] {
+    &b[x.start.pos as usize..x.end.pos as usize]
+}
+
 // Input Type
 const MPC_INPUT_STRING: usize = 0;
 const MPC_INPUT_FILE: usize = 1;
@@ -42,54 +70,193 @@ const MPC_INPUT_MARKS_MIN: usize = 32;
 
 const MPC_INPUT_MEM_NUM: usize = 512;
 
+const MPC_INPUT_LINE_MAX: i32 = 4096;
+
+const MPC_WORD_MAX: usize = 64;
+
+// Only ever written and read through raw pointers.
+#[allow(dead_code)]
+#[repr(align(16))]
 struct mpc_mem_t {
-    mem: [char; 64],
+    mem: [MaybeUninit<u8>; 64],
+}
+
+// Blocks for the many small values a parse produces and mostly throws away
+// again, allocated `MPC_INPUT_MEM_NUM` at a time and reused through a free
+// list. It is shared with the values living in it, so it outlives the input
+// if it has to.
+pub struct mpc_pool_t {
+    mem_free: RefCell<Vec<usize>>,
+    mem: RefCell<Vec<Box<[UnsafeCell<mpc_mem_t>]>>>,
+}
+
+// What a column counts, `mpc_col_width` being the display width with tabs
+// advancing to the next multiple of the tab stop given.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum mpc_col_mode_t {
+    mpc_col_chars,
+    mpc_col_bytes,
+    mpc_col_utf16,
+    mpc_col_graphemes,
+    mpc_col_width(i32),
+}
+
+// Byte offsets at which lines start, as far as the input has been scanned up
+// to `end`. `last` is the most recent position and column looked up, so that
+// walking along a line doesn't count it from its start every time. `cut` is
+// the position and column a pipe last dropped a long line up to.
+struct mpc_lines_t {
+    starts: Vec<i32>,
+    end: i32,
+    last: (i32, i32),
+    cut: (i32, i32),
+    mode: mpc_col_mode_t,
+}
+
+// Anything file input can read from and seek back in.
+trait mpc_file_t: Read + Seek {}
+impl<T: Read + Seek> mpc_file_t for T {}
+
+// A file read through a buffer which stepping back a character doesn't throw
+// away, as seeking a `BufReader` would. `pos` saves asking the file for it.
+struct mpc_file_buffered_t {
+    file: BufReader<File>,
+    pos: u64,
+}
+
+impl Read for mpc_file_buffered_t {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.file.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for mpc_file_buffered_t {
+    fn seek(&mut self, to: SeekFrom) -> std::io::Result<u64> {
+        self.pos = match to {
+            SeekFrom::Current(n) => {
+                self.file.seek_relative(n)?;
+                self.pos.saturating_add_signed(n)
+            }
+            _ => self.file.seek(to)?,
+        };
+        Ok(self.pos)
+    }
 }
 
-struct mpc_input_t {
+pub struct mpc_input_t<'a> {
     itype: usize,
     filename: String,
     state: mpc_state_t,
 
-    string: String,
-    buffer: Vec<char>,
-    file: Option<File>,
+    string: Box<dyn AsRef<[u8]> + 'a>,
+    buffer: Vec<u8>,
+    buffer_pos: i32,
+    file: Option<Box<dyn mpc_file_t + 'a>>,
+    pipe: Option<Box<dyn Read + 'a>>,
+    io_error: Option<mpc_err_t>,
 
     suppress: bool,
-    backtrack: bool,
-    marks_slot: i32,
-    marks_num: i32,
+    backtrack: i32,
     marks: Vec<mpc_state_t>,
 
     lasts: Vec<char>,
     last: char,
 
-    mem_index: usize,
-    mem_full: Vec<char>,
-    mem: Vec<mpc_mem_t>,
+    mem: Option<Rc<mpc_pool_t>>,
+
+    lines: mpc_lines_t,
+
+    errors: Vec<mpc_err_t>,
+
+    lr: HashMap<(*const mpc_parser_t, i32), mpc_lr_t>,
+}
+
+// A named parser being parsed at some position. Should it call itself there,
+// directly or through others, the call gets back `seed`, the best parse of it
+// found so far, along with the state and last character after it.
+struct mpc_lr_t {
+    used: bool,
+    seed: Option<(mpc_state_t, char, mpc_val_t)>,
+}
+
+pub fn mpc_input_new_string<'a>(filename: &str, string: &'a str) -> mpc_input_t<'a> {
+    let itype = MPC_INPUT_STRING;
+    let filename = filename.to_owned();
+    let state = mpc_state_new();
+
+    let string: Box<dyn AsRef<[u8]> + 'a> = Box::new(string.as_bytes());
+    let buffer = vec![];
+    let buffer_pos = 0;
+    let file = None;
+    let pipe = None;
+    let io_error = None;
+
+    let suppress = false;
+    let backtrack = 1;
+    let marks: Vec<mpc_state_t> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
+
+    let lasts: Vec<char> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
+    let last = '\0';
+
+    let mem = Some(mpc_mem_new());
+
+    let lines = mpc_lines_new();
+    let errors = vec![];
+    let lr = HashMap::new();
+
+    mpc_input_t {
+        itype,
+        filename,
+        state,
+        string,
+        buffer,
+        buffer_pos,
+        file,
+        pipe,
+        io_error,
+        suppress,
+        backtrack,
+        marks,
+        lasts,
+        last,
+        mem,
+        lines,
+        errors,
+        lr,
+    }
 }
 
-fn mpc_input_new_string(filename: &str, string: &str) -> mpc_input_t {
+pub fn mpc_input_new_nstring<'a>(
+    filename: &str,
+    string: &'a str,
+    length: usize,
+) -> mpc_input_t<'a> {
     let itype = MPC_INPUT_STRING;
     let filename = filename.to_owned();
     let state = mpc_state_new();
 
-    let string = string.to_owned();
+    let string: Box<dyn AsRef<[u8]> + 'a> =
+        Box::new(&string.as_bytes()[..length.min(string.len())]);
     let buffer = vec![];
+    let buffer_pos = 0;
     let file = None;
+    let pipe = None;
+    let io_error = None;
 
     let suppress = false;
-    let backtrack = false;
-    let marks_num = 0;
-    let marks_slot = MPC_INPUT_MARKS_MIN as i32;
-    let marks: Vec<mpc_state_t> = Vec::with_capacity(marks_slot as usize);
+    let backtrack = 1;
+    let marks: Vec<mpc_state_t> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
 
-    let lasts: Vec<char> = Vec::with_capacity(marks_slot as usize);
+    let lasts: Vec<char> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
     let last = '\0';
 
-    let mem_index = 0;
-    let mem_full: Vec<char> = vec!['\0'; MPC_INPUT_MEM_NUM];
-    let mem = Vec::with_capacity(MPC_INPUT_MEM_NUM);
+    let mem = Some(mpc_mem_new());
+
+    let lines = mpc_lines_new();
+    let errors = vec![];
+    let lr = HashMap::new();
 
     mpc_input_t {
         itype,
@@ -97,41 +264,46 @@ fn mpc_input_new_string(filename: &str, string: &str) -> mpc_input_t {
         state,
         string,
         buffer,
+        buffer_pos,
         file,
+        pipe,
+        io_error,
         suppress,
         backtrack,
-        marks_slot,
-        marks_num,
         marks,
         lasts,
         last,
-        mem_index,
-        mem_full,
         mem,
+        lines,
+        errors,
+        lr,
     }
 }
 
-fn mpc_input_new_nstring(filename: &str, string: &str, length: usize) -> mpc_input_t {
+pub fn mpc_input_new_bytes<'a>(filename: &str, bytes: &'a [u8]) -> mpc_input_t<'a> {
     let itype = MPC_INPUT_STRING;
     let filename = filename.to_owned();
     let state = mpc_state_new();
 
-    let string = string[..length].to_owned();
+    let string: Box<dyn AsRef<[u8]> + 'a> = Box::new(bytes);
     let buffer = vec![];
+    let buffer_pos = 0;
     let file = None;
+    let pipe = None;
+    let io_error = None;
 
     let suppress = false;
-    let backtrack = false;
-    let marks_num = 0;
-    let marks_slot = MPC_INPUT_MARKS_MIN as i32;
-    let marks: Vec<mpc_state_t> = Vec::with_capacity(marks_slot as usize);
+    let backtrack = 1;
+    let marks: Vec<mpc_state_t> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
 
-    let lasts: Vec<char> = Vec::with_capacity(marks_slot as usize);
+    let lasts: Vec<char> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
     let last = '\0';
 
-    let mem_index = 0;
-    let mem_full: Vec<char> = vec!['\0'; MPC_INPUT_MEM_NUM];
-    let mem = Vec::with_capacity(MPC_INPUT_MEM_NUM);
+    let mem = Some(mpc_mem_new());
+
+    let lines = mpc_lines_new();
+    let errors = vec![];
+    let lr = HashMap::new();
 
     mpc_input_t {
         itype,
@@ -139,41 +311,46 @@ fn mpc_input_new_nstring(filename: &str, string: &str, length: usize) -> mpc_inp
         state,
         string,
         buffer,
+        buffer_pos,
         file,
+        pipe,
+        io_error,
         suppress,
         backtrack,
-        marks_slot,
-        marks_num,
         marks,
         lasts,
         last,
-        mem_index,
-        mem_full,
         mem,
+        lines,
+        errors,
+        lr,
     }
 }
 
-fn mpc_input_new_pipe(filename: &str, pipe: File) -> mpc_input_t {
+pub fn mpc_input_new_pipe<'a, R: Read + 'a>(filename: &str, pipe: R) -> mpc_input_t<'a> {
     let itype = MPC_INPUT_PIPE;
     let filename = filename.to_owned();
     let state = mpc_state_new();
 
-    let string = String::new();
+    let string: Box<dyn AsRef<[u8]> + 'a> = Box::new([]);
     let buffer = vec![];
-    let file: Option<File> = Some(pipe);
+    let buffer_pos = 0;
+    let file = None;
+    let pipe: Option<Box<dyn Read + 'a>> = Some(Box::new(pipe));
+    let io_error = None;
 
     let suppress = false;
-    let backtrack = false;
-    let marks_num = 0;
-    let marks_slot = MPC_INPUT_MARKS_MIN as i32;
-    let marks: Vec<mpc_state_t> = Vec::with_capacity(marks_slot as usize);
+    let backtrack = 1;
+    let marks: Vec<mpc_state_t> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
 
-    let lasts: Vec<char> = Vec::with_capacity(marks_slot as usize);
+    let lasts: Vec<char> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
     let last = '\0';
 
-    let mem_index = 0;
-    let mem_full: Vec<char> = vec!['\0'; MPC_INPUT_MEM_NUM];
-    let mem = Vec::with_capacity(MPC_INPUT_MEM_NUM);
+    let mem = Some(mpc_mem_new());
+
+    let lines = mpc_lines_new();
+    let errors = vec![];
+    let lr = HashMap::new();
 
     mpc_input_t {
         itype,
@@ -181,41 +358,46 @@ fn mpc_input_new_pipe(filename: &str, pipe: File) -> mpc_input_t {
         state,
         string,
         buffer,
+        buffer_pos,
         file,
+        pipe,
+        io_error,
         suppress,
         backtrack,
-        marks_slot,
-        marks_num,
         marks,
         lasts,
         last,
-        mem_index,
-        mem_full,
         mem,
+        lines,
+        errors,
+        lr,
     }
 }
 
-fn mpc_input_new_file(filename: &str, file: File) -> mpc_input_t {
-    let itype = MPC_INPUT_STRING;
+pub fn mpc_input_new_file<'a, R: Read + Seek + 'a>(filename: &str, file: R) -> mpc_input_t<'a> {
+    let itype = MPC_INPUT_FILE;
     let filename = filename.to_owned();
     let state = mpc_state_new();
 
-    let string = String::new();
+    let string: Box<dyn AsRef<[u8]> + 'a> = Box::new([]);
     let buffer = vec![];
-    let file = Some(file);
+    let buffer_pos = 0;
+    let file: Option<Box<dyn mpc_file_t + 'a>> = Some(Box::new(file));
+    let pipe = None;
+    let io_error = None;
 
     let suppress = false;
-    let backtrack = false;
-    let marks_num = 0;
-    let marks_slot = MPC_INPUT_MARKS_MIN as i32;
-    let marks: Vec<mpc_state_t> = Vec::with_capacity(marks_slot as usize);
+    let backtrack = 1;
+    let marks: Vec<mpc_state_t> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
 
-    let lasts: Vec<char> = Vec::with_capacity(marks_slot as usize);
+    let lasts: Vec<char> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
     let last = '\0';
 
-    let mem_index = 0;
-    let mem_full: Vec<char> = vec!['\0'; MPC_INPUT_MEM_NUM as usize];
-    let mem = Vec::with_capacity(MPC_INPUT_MEM_NUM as usize);
+    let mem = Some(mpc_mem_new());
+
+    let lines = mpc_lines_new();
+    let errors = vec![];
+    let lr = HashMap::new();
 
     mpc_input_t {
         itype,
@@ -223,54 +405,174 @@ fn mpc_input_new_file(filename: &str, file: File) -> mpc_input_t {
         state,
         string,
         buffer,
+        buffer_pos,
+        file,
+        pipe,
+        io_error,
+        suppress,
+        backtrack,
+        marks,
+        lasts,
+        last,
+        mem,
+        lines,
+        errors,
+        lr,
+    }
+}
+
+/// Maps a regular file into memory instead of reading it. Offsets are `i32`,
+/// so files of 2 GiB or more are refused.
+///
+/// # Safety
+///
+/// The file must not be modified, by this process or any other, for as long
+/// as the input is alive.
+#[cfg(feature = "mmap")]
+pub unsafe fn mpc_input_new_mmap(
+    filename: &str,
+    file: &File,
+) -> std::io::Result<mpc_input_t<'static>> {
+    if file.metadata()?.len() > i32::MAX as u64 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "file too large",
+        ));
+    }
+
+    let itype = MPC_INPUT_STRING;
+    let filename = filename.to_owned();
+    let state = mpc_state_new();
+
+    let string: Box<dyn AsRef<[u8]>> = Box::new(unsafe { memmap2::Mmap::map(file)? });
+    let buffer = vec![];
+    let buffer_pos = 0;
+    let file = None;
+    let pipe = None;
+    let io_error = None;
+
+    let suppress = false;
+    let backtrack = 1;
+    let marks: Vec<mpc_state_t> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
+
+    let lasts: Vec<char> = Vec::with_capacity(MPC_INPUT_MARKS_MIN);
+    let last = '\0';
+
+    let mem = Some(mpc_mem_new());
+
+    let lines = mpc_lines_new();
+    let errors = vec![];
+    let lr = HashMap::new();
+
+    Ok(mpc_input_t {
+        itype,
+        filename,
+        state,
+        string,
+        buffer,
+        buffer_pos,
         file,
+        pipe,
+        io_error,
         suppress,
         backtrack,
-        marks_slot,
-        marks_num,
         marks,
         lasts,
         last,
-        mem_index,
-        mem_full,
         mem,
+        lines,
+        errors,
+        lr,
+    })
+}
+
+fn mpc_lines_new() -> mpc_lines_t {
+    mpc_lines_t {
+        starts: vec![0],
+        end: 0,
+        last: (0, 0),
+        cut: (0, 0),
+        mode: mpc_col_mode_t::mpc_col_chars,
     }
 }
 
-fn mpc_input_delete(i: &mut mpc_input_t) {
-    drop(i)
+fn mpc_mem_new() -> Rc<mpc_pool_t> {
+    Rc::new(mpc_pool_t {
+        mem_free: RefCell::new(vec![]),
+        mem: RefCell::new(vec![]),
+    })
 }
 
-fn is_ptr_inside<T>(p: *const T, q: &Vec<T>) -> bool {
-    let q_start = q.as_ptr() as usize;
-    let q_end = q_start + q.len() * std::mem::size_of::<T>();
-    let p_addr = p as usize;
-    p_addr >= q_start && p_addr < q_end
+fn mpc_mem_alloc(m: &mpc_pool_t) -> (usize, *mut u8) {
+    let mut free = m.mem_free.borrow_mut();
+    let mut mem = m.mem.borrow_mut();
+
+    if free.is_empty() {
+        let n = mem.len() * MPC_INPUT_MEM_NUM;
+        let block = || {
+            UnsafeCell::new(mpc_mem_t {
+                mem: [MaybeUninit::uninit(); 64],
+            })
+        };
+        mem.push((0..MPC_INPUT_MEM_NUM).map(|_| block()).collect());
+        free.extend((n..n + MPC_INPUT_MEM_NUM).rev());
+    }
+
+    let k = free.pop().unwrap();
+    let p = mem[k / MPC_INPUT_MEM_NUM][k % MPC_INPUT_MEM_NUM].get() as *mut u8;
+    (k, p)
 }
 
-// TODO: Implemented Naively, more work needed.
-fn mpc_mem_ptr(i: &mpc_input_t, p: *const u8) -> bool {
-    let start = i.mem.as_ptr() as *const u8;
-    let end = unsafe { start.add(MPC_INPUT_MEM_NUM * std::mem::size_of::<mpc_mem_t>()) };
-    p >= start && p < end
+fn mpc_mem_free(m: &mpc_pool_t, k: usize) {
+    m.mem_free.borrow_mut().push(k);
 }
 
-// mpc_malloc, mpc_calloc, mpc_realloc and mpc_free are skipped as they don't need to implemented because Rust handle them gracefully.
+// Values too big for a block, or produced while the pool is disabled, go to
+// the heap instead.
+fn mpc_malloc<O: 'static>(i: &mpc_input_t, x: O) -> mpc_val_t {
+    let fits = std::mem::size_of::<O>() <= std::mem::size_of::<mpc_mem_t>()
+        && std::mem::align_of::<O>() <= std::mem::align_of::<mpc_mem_t>();
 
-// TODO: Implemented Naively, more work needed.
-fn mpc_export(i: &mut mpc_input_t, p: *const u8) {
-    if mpc_mem_ptr(i, p) {
-        return;
+    if let Some(m) = i.mem.as_ref().filter(|_| fits) {
+        let (k, p) = mpc_mem_alloc(m);
+        unsafe { ptr::write(p as *mut O, x) };
+        return mpc_val_t {
+            ptr: p,
+            type_id: TypeId::of::<O>(),
+            drop: mpc_val_drop::<O>,
+            pool: Some((m.clone(), k)),
+        };
+    }
+
+    mpc_val_t {
+        ptr: Box::into_raw(Box::new(x)) as *mut u8,
+        type_id: TypeId::of::<O>(),
+        drop: mpc_val_drop::<O>,
+        pool: None,
+    }
+}
+
+// Moves a value which survives into the result out of the pool.
+fn mpc_export<O: 'static>(x: mpc_val_t) -> O {
+    mpc_val_take(x)
+}
+
+pub fn mpc_input_pool_disable(i: &mut mpc_input_t) {
+    i.mem = None;
+}
+
+pub fn mpc_input_pool_enable(i: &mut mpc_input_t) {
+    if i.mem.is_none() {
+        i.mem = Some(mpc_mem_new());
     }
-    unimplemented!()
 }
 
 fn mpc_input_backtrack_disable(i: &mut mpc_input_t) {
-    i.backtrack = false;
+    i.backtrack -= 1;
 }
 
 fn mpc_input_backtrack_enable(i: &mut mpc_input_t) {
-    i.backtrack = true;
+    i.backtrack += 1;
 }
 
 fn mpc_input_suppress_disable(i: &mut mpc_input_t) {
@@ -282,974 +584,4831 @@ fn mpc_input_suppress_enable(i: &mut mpc_input_t) {
 }
 
 fn mpc_input_mark(i: &mut mpc_input_t) {
-    if !i.backtrack {
+    if i.backtrack < 1 {
         return;
     }
 
-    i.marks_num += 1;
+    i.marks.push(i.state);
+    i.lasts.push(i.last);
+}
 
-    if i.marks_num > i.marks_slot {
-        i.marks_slot = i.marks_num + i.marks_num / 2;
-        i.marks.resize(i.marks_slot as usize, Default::default());
-        i.lasts.resize(i.marks_slot as usize, Default::default());
+fn mpc_input_unmark(i: &mut mpc_input_t) {
+    if i.backtrack < 1 {
+        return;
     }
 
-    i.marks[(i.marks_num - 1) as usize] = i.state;
-    i.lasts[(i.marks_num - 1) as usize] = i.last;
+    i.marks.pop();
+    i.lasts.pop();
 
-    if i.itype == MPC_INPUT_PIPE && i.marks_num == 1 {
-        i.buffer.resize(1, Default::default())
-    }
+    mpc_input_buffer_release(i);
 }
 
-fn mpc_input_unmark(i: &mut mpc_input_t) {
-    if !i.backtrack {
+fn mpc_input_rewind(i: &mut mpc_input_t) {
+    if i.backtrack < 1 {
         return;
     }
 
-    if i.marks_slot > i.marks_num + i.marks_num / 2 && i.marks_slot > MPC_INPUT_MARKS_MIN as i32 {
-        i.marks_slot = if i.marks_num > MPC_INPUT_MARKS_MIN as i32 {
-            i.marks_num
-        } else {
-            MPC_INPUT_MARKS_MIN as i32
-        }
+    i.state = i.marks[i.marks.len() - 1];
+    i.last = i.lasts[i.lasts.len() - 1];
+
+    if i.itype == MPC_INPUT_FILE {
+        mpc_input_seek(i, SeekFrom::Start(i.state.pos as u64));
     }
 
-    if i.itype == MPC_INPUT_PIPE && i.marks_num == 0 {
-        if i.file.is_none() {
-            panic!("Error: No File Opened.")
-        } else {
-            let f = i.file.as_mut().unwrap();
-            for idx in (0..i.buffer.len()).rev() {
-                match write!(f, "{}", i.buffer[idx]) {
-                    Ok(_) => (),
-                    Err(e) => panic!("Error: {e} occured."),
-                }
-            }
+    mpc_input_unmark(i);
+}
 
-            i.buffer.clear();
-        }
-    }
+// Marking and rewinding even with backtracking disabled, for the few parsers
+// which need to get back to where they were regardless.
+fn mpc_input_mark_always(i: &mut mpc_input_t) {
+    let backtrack = std::mem::replace(&mut i.backtrack, 1);
+    mpc_input_mark(i);
+    i.backtrack = backtrack;
 }
 
-fn mpc_input_rewind(i: &mut mpc_input_t) {
-    if !i.backtrack {
-        return;
-    }
+fn mpc_input_unmark_always(i: &mut mpc_input_t) {
+    let backtrack = std::mem::replace(&mut i.backtrack, 1);
+    mpc_input_unmark(i);
+    i.backtrack = backtrack;
+}
+
+fn mpc_input_rewind_always(i: &mut mpc_input_t) {
+    let backtrack = std::mem::replace(&mut i.backtrack, 1);
+    mpc_input_rewind(i);
+    i.backtrack = backtrack;
+}
 
-    i.state = i.marks[(i.marks_num - 1) as usize];
-    i.last = i.lasts[(i.marks_num - 1) as usize];
+// Moves back to a position read up to before, which a mark still holds on to.
+fn mpc_input_jump(i: &mut mpc_input_t, s: mpc_state_t, last: char) {
+    i.state = s;
+    i.last = last;
 
     if i.itype == MPC_INPUT_FILE {
-        i.file
-            .as_ref()
-            .unwrap()
-            .seek(SeekFrom::Start(i.state.pos as u64))
-            .unwrap();
+        mpc_input_seek(i, SeekFrom::Start(i.state.pos as u64));
     }
+}
 
-    mpc_input_mark(i);
+// Pipes cannot seek, so everything read from them goes through a replay buffer
+// starting at byte offset `buffer_pos`. It is filled on demand and only keeps
+// bytes behind the current position while marks are outstanding.
+fn mpc_input_buffer_fill<'a>(i: &'a mut mpc_input_t, n: usize) -> &'a [u8] {
+    let k = (i.state.pos - i.buffer_pos) as usize;
+    let mut buf = [0u8; 4096];
+
+    while i.buffer.len() < k + n {
+        if i.io_error.is_some() {
+            break;
+        }
+        match i.pipe.as_mut().unwrap().read(&mut buf) {
+            Ok(0) => break,
+            Ok(m) => i.buffer.extend_from_slice(&buf[..m]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(e) => mpc_input_io_error(i, e),
+        }
+    }
+
+    &i.buffer[k.min(i.buffer.len())..]
+}
+
+fn mpc_input_seek(i: &mut mpc_input_t, to: SeekFrom) -> Option<u64> {
+    match i.file.as_mut().unwrap().seek(to) {
+        Ok(x) => Some(x),
+        Err(e) => {
+            mpc_input_io_error(i, e);
+            None
+        }
+    }
 }
 
-fn mpc_input_buffer_in_range(i: &mpc_input_t) -> bool {
-    i.state.pos < (i.buffer.len() as i32 + i.marks[0].pos)
+// Failing to read or seek ends the input there, but the parse then fails with
+// the first such error whatever it came to otherwise.
+fn mpc_input_io_error(i: &mut mpc_input_t, e: std::io::Error) {
+    if i.io_error.is_none() {
+        let failure = format!("Unable to read input: {e}");
+        i.io_error = Some(mpc_err_t {
+            state: i.state,
+            ..mpc_err_file(&i.filename, &failure)
+        });
+    }
 }
 
-fn mpc_input_buffer_get(i: &mpc_input_t) -> char {
-    i.buffer[(i.state.pos - i.marks[0].pos) as usize]
+// The current line is kept as well for error messages, unless that means
+// keeping more than `MPC_INPUT_LINE_MAX` bytes of it. Then it is dropped too,
+// its column noted so that columns further along can still be counted.
+fn mpc_input_buffer_release(i: &mut mpc_input_t) {
+    if i.itype != MPC_INPUT_PIPE || !i.marks.is_empty() {
+        return;
+    }
+
+    let pos = i.state.pos;
+    let (_, mut start) = mpc_input_line_start(i, pos);
+    if pos - start.max(i.buffer_pos) > MPC_INPUT_LINE_MAX {
+        i.lines.cut = (pos, mpc_input_col(i, start, pos));
+        start = pos;
+    }
+
+    let k = (start - i.buffer_pos).max(0) as usize;
+    i.buffer.drain(..k.min(i.buffer.len()));
+    i.buffer_pos = i.buffer_pos.max(start);
 }
 
-fn mpc_input_getc(i: &mpc_input_t) -> char {
+// Line Index
+
+// Hands `f` the input bytes from `start` up to `end`, or as many of them as
+// there are, without moving the input.
+fn mpc_input_with_bytes<T>(
+    i: &mut mpc_input_t,
+    start: i32,
+    end: i32,
+    f: impl FnOnce(&[u8]) -> T,
+) -> T {
     if i.itype == MPC_INPUT_STRING {
-        return i.string.chars().nth(i.state.pos as usize).unwrap();
-    } else if i.itype == MPC_INPUT_FILE {
-        let mut buf = [0u8; 1];
-        i.file.as_ref().unwrap().read(&mut buf).unwrap();
-        return buf[0] as char;
+        let b = (*i.string).as_ref();
+        let e = (end.max(0) as usize).min(b.len());
+        f(&b[(start.max(0) as usize).min(e)..e])
     } else if i.itype == MPC_INPUT_PIPE {
-        if i.buffer.len() > 0 && mpc_input_buffer_in_range(i) {
-            return mpc_input_buffer_get(i);
-        } else {
-            let mut buf = [0u8; 1];
-            i.file.as_ref().unwrap().read(&mut buf).unwrap();
-            return buf[0] as char;
+        if start < i.buffer_pos {
+            return f(&[]);
         }
+        mpc_input_buffer_fill(i, (end - i.state.pos).max(0) as usize);
+        let e = ((end - i.buffer_pos) as usize).min(i.buffer.len());
+        f(&i.buffer[((start - i.buffer_pos) as usize).min(e)..e])
     } else {
-        return '\0';
+        let here = match mpc_input_seek(i, SeekFrom::Current(0)) {
+            Some(here) => here,
+            None => return f(&[]),
+        };
+        let mut b = vec![];
+        if mpc_input_seek(i, SeekFrom::Start(start as u64)).is_some() {
+            let file = i.file.as_mut().unwrap();
+            let n = (end - start).max(0) as u64;
+            if let Err(e) = file.by_ref().take(n).read_to_end(&mut b) {
+                mpc_input_io_error(i, e);
+            }
+        }
+        mpc_input_seek(i, SeekFrom::Start(here));
+        f(&b)
     }
 }
 
-fn mpc_input_peekc(i: &mpc_input_t) -> char {
-    if i.itype == MPC_INPUT_STRING {
-        return i.string.chars().nth(i.state.pos as usize).unwrap();
-    } else if i.itype == MPC_INPUT_FILE {
-        let mut buf = [0u8; 1];
-        let bytes_read = i.file.as_ref().unwrap().read(&mut buf).unwrap();
+fn mpc_input_lines_scan(i: &mut mpc_input_t, pos: i32) {
+    let start = i.lines.end;
+    if pos <= start {
+        return;
+    }
 
-        if bytes_read == 0 {
-            return '\0';
+    let (starts, n) = mpc_input_with_bytes(i, start, pos, |b| {
+        let starts: Vec<i32> = b
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c == b'\n')
+            .map(|(k, _)| start + k as i32 + 1)
+            .collect();
+        (starts, b.len() as i32)
+    });
+
+    i.lines.starts.extend(starts);
+    i.lines.end = start + n;
+}
+
+// The row `pos` is on and the offset that row starts at.
+fn mpc_input_line_start(i: &mut mpc_input_t, pos: i32) -> (i32, i32) {
+    mpc_input_lines_scan(i, pos);
+    let row = i.lines.starts.partition_point(|s| *s <= pos) - 1;
+    (row as i32, i.lines.starts[row])
+}
+
+// Grapheme clusters can join across the position last looked up, so they are
+// always counted from the start of the line, or where a pipe cut it short.
+fn mpc_input_col(i: &mut mpc_input_t, start: i32, pos: i32) -> i32 {
+    let mode = i.lines.mode;
+    let (last, col) = i.lines.last;
+    let (cut, cut_col) = i.lines.cut;
+    let (from, col) =
+        if last >= start.max(cut) && last <= pos && mode != mpc_col_mode_t::mpc_col_graphemes {
+            (last, col)
+        } else if cut > start && cut <= pos {
+            (cut, cut_col)
+        } else {
+            (start, 0)
+        };
+
+    let col = mpc_input_with_bytes(i, from, pos, |b| mpc_col_advance(mode, col, b));
+
+    i.lines.last = (pos, col);
+    col
+}
+
+fn mpc_col_advance(mode: mpc_col_mode_t, col: i32, b: &[u8]) -> i32 {
+    match mode {
+        mpc_col_mode_t::mpc_col_chars => {
+            col + b.iter().filter(|c| (**c & 0xC0) != 0x80).count() as i32
+        }
+        mpc_col_mode_t::mpc_col_bytes => col + b.len() as i32,
+        mpc_col_mode_t::mpc_col_utf16 => {
+            col + String::from_utf8_lossy(b)
+                .chars()
+                .map(|c| c.len_utf16() as i32)
+                .sum::<i32>()
+        }
+        mpc_col_mode_t::mpc_col_graphemes => {
+            col + String::from_utf8_lossy(b).graphemes(true).count() as i32
+        }
+        mpc_col_mode_t::mpc_col_width(tab) => {
+            String::from_utf8_lossy(b)
+                .chars()
+                .fold(col, |col, c| match c {
+                    '\t' if tab > 0 => (col / tab + 1) * tab,
+                    _ => col + c.width().unwrap_or(0) as i32,
+                })
         }
+    }
+}
 
-        i.file
-            .as_ref()
-            .unwrap()
-            .seek(SeekFrom::Current(-1))
-            .unwrap();
+pub fn mpc_input_col_mode(i: &mut mpc_input_t, mode: mpc_col_mode_t) {
+    i.lines.mode = mode;
+    i.lines.last = (0, 0);
+}
 
-        return buf[0] as char;
-    } else if i.itype == MPC_INPUT_PIPE {
-        if i.buffer.len() > 0 && mpc_input_buffer_in_range(i) {
-            return mpc_input_buffer_get(i);
-        } else {
-            let mut buf = [0u8; 1];
-            let bytes_read = i.file.as_ref().unwrap().read(&mut buf).unwrap();
+pub fn mpc_input_state_at(i: &mut mpc_input_t, pos: i32) -> mpc_state_t {
+    let (row, start) = mpc_input_line_start(i, pos);
+    let col = mpc_input_col(i, start, pos);
+    mpc_state_t {
+        pos,
+        row,
+        col,
+        term: 0,
+    }
+}
 
-            if bytes_read == 0 {
-                return '\0';
-            }
-            let c = buf[0] as char;
-            write!(i.file.as_ref().unwrap(), "{c}").unwrap();
-            return c;
+fn mpc_input_state(i: &mut mpc_input_t) -> mpc_state_t {
+    mpc_input_decode(i);
+    let mut s = mpc_input_state_at(i, i.state.pos);
+    s.term = i.state.term;
+    s
+}
+
+// The text of a line without its line ending, if the input still has it.
+pub fn mpc_input_line(i: &mut mpc_input_t, row: i32) -> Option<String> {
+    let row = usize::try_from(row).ok()?;
+    while i.lines.starts.len() <= row + 1 {
+        let end = i.lines.end;
+        mpc_input_lines_scan(i, end + 4096);
+        if i.lines.end == end {
+            break;
         }
-    } else {
-        return '\0';
     }
+
+    let start = *i.lines.starts.get(row)?;
+    if i.itype == MPC_INPUT_PIPE && start < i.buffer_pos {
+        return None;
+    }
+
+    let end = i.lines.starts.get(row + 1).map_or(i.lines.end, |e| e - 1);
+    let line = mpc_input_with_bytes(i, start, end, |b| String::from_utf8_lossy(b).into_owned());
+    Some(line.strip_suffix('\r').unwrap_or(&line).to_owned())
 }
 
-fn mpc_input_terminated(i: &mpc_input_t) -> bool {
-    mpc_input_peekc(i) == '\0'
+// UTF-8 Decoding
+
+#[allow(clippy::upper_case_acronyms)]
+enum mpc_decoded_t {
+    END,
+    CHAR(char),
+    INVALID,
 }
 
-fn mpc_input_failure(i: &mpc_input_t, ch: char) -> u8 {
-    match i.itype {
-        MPC_INPUT_FILE => {
-            i.file
-                .as_ref()
-                .unwrap()
-                .seek(SeekFrom::Current(-1))
-                .unwrap();
+// Length of the sequence introduced by a leading byte, 0 if it cannot start one.
+fn mpc_utf8_width(b: u8) -> usize {
+    match b {
+        0x00..=0x7F => 1,
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => 0,
+    }
+}
+
+fn mpc_utf8_decode(b: &[u8]) -> mpc_decoded_t {
+    let n = match b.first() {
+        Some(&x) => mpc_utf8_width(x),
+        None => return mpc_decoded_t::END,
+    };
+
+    match b.get(..n).map(std::str::from_utf8) {
+        Some(Ok(s)) => s
+            .chars()
+            .next()
+            .map_or(mpc_decoded_t::INVALID, mpc_decoded_t::CHAR),
+        _ => mpc_decoded_t::INVALID,
+    }
+}
+
+// Reads the bytes of a single character from the underlying file, fewer if it
+// is exhausted or the leading byte is not valid.
+fn mpc_input_read(i: &mut mpc_input_t) -> Vec<u8> {
+    let mut b = Vec::with_capacity(4);
+
+    while b.len() < b.first().map_or(1, |&x| mpc_utf8_width(x).max(1)) {
+        match mpc_input_read_byte(i) {
+            Some(x) => b.push(x),
+            None => break,
         }
-        MPC_INPUT_PIPE => {
-            if i.buffer.is_empty() || (i.buffer.len() > 0 && !mpc_input_buffer_in_range(i)) {
-                write!(i.file.as_ref().unwrap(), "{ch}").unwrap();
-            }
+    }
+
+    b
+}
+
+fn mpc_input_read_byte(i: &mut mpc_input_t) -> Option<u8> {
+    let mut buf = [0u8; 1];
+    while i.io_error.is_none() {
+        match i.file.as_mut().unwrap().read(&mut buf) {
+            Ok(0) => return None,
+            Ok(_) => return Some(buf[0]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(e) => mpc_input_io_error(i, e),
         }
-        _ => (),
     }
-    0
+    None
 }
 
-fn mpc_input_success(i: &mut mpc_input_t, c: char, o: Vec<&str>) -> u8 {
-    if i.itype == MPC_INPUT_PIPE && !i.buffer.is_empty() && !mpc_input_buffer_in_range(i) {
-        let s = i.buffer.len();
-        i.buffer.resize(s + 2, Default::default());
-        let s = i.buffer.len();
-        i.buffer[s + 1] = '\0';
-        i.buffer[s + 0] = c;
+// Gives back bytes taken by `mpc_input_read` which were not consumed.
+fn mpc_input_unread(i: &mut mpc_input_t, b: &[u8]) {
+    if b.is_empty() || i.itype != MPC_INPUT_FILE {
+        return;
     }
 
-    i.last = c;
-    i.state.pos += 1;
-    i.state.col += 1;
+    mpc_input_seek(i, SeekFrom::Current(-(b.len() as i64)));
+}
+
+// Decodes the next character without consuming it, noting in `term` whether
+// the end of the input has been reached.
+fn mpc_input_decode(i: &mut mpc_input_t) -> mpc_decoded_t {
+    let x = mpc_input_decode_next(i);
+    i.state.term = matches!(x, mpc_decoded_t::END) as i32;
+    x
+}
+
+fn mpc_input_decode_next(i: &mut mpc_input_t) -> mpc_decoded_t {
+    if i.itype == MPC_INPUT_STRING {
+        mpc_utf8_decode(
+            (*i.string)
+                .as_ref()
+                .get(i.state.pos as usize..)
+                .unwrap_or_default(),
+        )
+    } else if i.itype == MPC_INPUT_PIPE {
+        let n = match mpc_input_buffer_fill(i, 1).first() {
+            Some(&b) => mpc_utf8_width(b).max(1),
+            None => return mpc_decoded_t::END,
+        };
+        mpc_utf8_decode(mpc_input_buffer_fill(i, n))
+    } else if i.itype == MPC_INPUT_FILE {
+        let b = mpc_input_read(i);
+        mpc_input_unread(i, &b);
+        mpc_utf8_decode(&b)
+    } else {
+        mpc_decoded_t::END
+    }
+}
 
-    if c == '\n' {
-        i.state.col = 0;
-        i.state.row += 1;
+fn mpc_input_getc(i: &mut mpc_input_t) -> char {
+    if i.itype != MPC_INPUT_FILE {
+        return mpc_input_peekc(i);
     }
 
-    if !o.is_empty() {
-        // TODO
-        unimplemented!()
+    match mpc_utf8_decode(&mpc_input_read(i)) {
+        mpc_decoded_t::CHAR(c) => c,
+        _ => '\0',
+    }
+}
+
+fn mpc_input_peekc(i: &mut mpc_input_t) -> char {
+    match mpc_input_decode(i) {
+        mpc_decoded_t::CHAR(c) => c,
+        _ => '\0',
+    }
+}
+
+fn mpc_input_terminated(i: &mut mpc_input_t) -> bool {
+    !matches!(mpc_input_decode(i), mpc_decoded_t::CHAR(_))
+}
+
+fn mpc_input_failure(i: &mut mpc_input_t, ch: char) -> u8 {
+    mpc_input_unread(i, ch.encode_utf8(&mut [0; 4]).as_bytes());
+    0
+}
+
+fn mpc_input_success(i: &mut mpc_input_t, c: char, o: Option<&mut String>) -> u8 {
+    i.last = c;
+    i.state.pos += c.len_utf8() as i32;
+
+    mpc_input_buffer_release(i);
+
+    if let Some(o) = o {
+        o.push(c);
     }
     1
 }
 
-fn mpc_input_any(i: &mut mpc_input_t, o: Vec<&str>) -> u8 {
+fn mpc_input_any(i: &mut mpc_input_t, o: Option<&mut String>) -> u8 {
     if mpc_input_terminated(i) {
         0
     } else {
-        mpc_input_success(i, mpc_input_getc(i), o)
+        let x = mpc_input_getc(i);
+        mpc_input_success(i, x, o)
     }
 }
 
-fn mpc_input_char(i: &mut mpc_input_t, c: char, o: Vec<&str>) -> u8 {
+fn mpc_input_char(i: &mut mpc_input_t, c: char, o: Option<&mut String>) -> u8 {
     if mpc_input_terminated(i) {
         return 0;
     }
     let x = mpc_input_getc(i);
 
-    return if x == c {
+    if x == c {
         mpc_input_success(i, x, o)
     } else {
-        mpc_input_failure(i, c)
-    };
+        mpc_input_failure(i, x)
+    }
 }
 
-fn mpc_input_range(i: &mut mpc_input_t, c: char, d: char, o: Vec<&str>) -> u8 {
+fn mpc_input_range(i: &mut mpc_input_t, c: char, d: char, o: Option<&mut String>) -> u8 {
     if mpc_input_terminated(i) {
         return 0;
     }
 
     let x = mpc_input_getc(i);
 
-    return if x >= c && x <= d {
+    if x >= c && x <= d {
         mpc_input_success(i, x, o)
     } else {
         mpc_input_failure(i, x)
-    };
+    }
 }
 
-fn mpc_input_oneof(i: &mut mpc_input_t, c: &str, o: Vec<&str>) -> u8 {
+fn mpc_input_oneof(i: &mut mpc_input_t, c: &str, o: Option<&mut String>) -> u8 {
     if mpc_input_terminated(i) {
         return 0;
     }
 
     let x = mpc_input_getc(i);
-    return if c.contains(x) {
+    if c.contains(x) {
         mpc_input_success(i, x, o)
     } else {
         mpc_input_failure(i, x)
-    };
+    }
 }
 
-fn mpc_input_noneof(i: &mut mpc_input_t, c: &str, o: Vec<&str>) -> u8 {
+fn mpc_input_noneof(i: &mut mpc_input_t, c: &str, o: Option<&mut String>) -> u8 {
     if mpc_input_terminated(i) {
         return 0;
     }
 
     let x = mpc_input_getc(i);
-    return if !c.contains(x) {
+    if !c.contains(x) {
         mpc_input_success(i, x, o)
     } else {
         mpc_input_failure(i, x)
-    };
+    }
 }
 
-fn mpc_input_satisfy(i: &mut mpc_input_t, cond: fn(char) -> bool, o: Vec<&str>) -> u8 {
+fn mpc_input_satisfy(i: &mut mpc_input_t, cond: fn(char) -> bool, o: Option<&mut String>) -> u8 {
     if mpc_input_terminated(i) {
         return 0;
     }
 
     let x = mpc_input_getc(i);
 
-    return if cond(x) {
+    if cond(x) {
         mpc_input_success(i, x, o)
     } else {
         mpc_input_failure(i, x)
-    };
+    }
+}
+
+fn mpc_input_string(i: &mut mpc_input_t, c: &str, o: Option<&mut String>) -> u8 {
+    mpc_input_string_by(i, c, |x, y| x == y, o)
 }
 
-fn mpc_input_string(i: &mut mpc_input_t, c: &str, o: Vec<&str>) -> u8 {
-    let x = Box::new(c);
+// Compares per character, so only the simple one to one case mappings apply.
+fn mpc_input_string_nocase(i: &mut mpc_input_t, c: &str, o: Option<&mut String>) -> u8 {
+    mpc_input_string_by(i, c, |x, y| x.to_lowercase().eq(y.to_lowercase()), o)
+}
 
+fn mpc_input_string_by(
+    i: &mut mpc_input_t,
+    c: &str,
+    eq: fn(char, char) -> bool,
+    mut o: Option<&mut String>,
+) -> u8 {
+    let n = o.as_ref().map_or(0, |o| o.len());
     mpc_input_mark(i);
 
-    unimplemented!();
+    for y in c.chars() {
+        let x = if mpc_input_terminated(i) {
+            None
+        } else {
+            Some(mpc_input_getc(i))
+        };
 
-    mpc_input_unmark(i);
+        match x {
+            Some(x) if eq(x, y) => {
+                mpc_input_success(i, x, o.as_deref_mut());
+            }
+            _ => {
+                if let Some(x) = x {
+                    mpc_input_failure(i, x);
+                }
+                mpc_input_rewind(i);
+                if let Some(o) = o {
+                    o.truncate(n);
+                }
+                return 0;
+            }
+        }
+    }
 
-    return 1;
+    mpc_input_unmark(i);
+    1
 }
 
-fn mpc_input_anchor(i: &mpc_input_t, f: fn(char, char) -> u8, o: Vec<Option<&str>>) -> u8 {
-    f(i.last, mpc_input_peekc(i))
+// Candidates are kept longest first, so the first one to match is the
+// longest match.
+fn mpc_input_string_oneof(i: &mut mpc_input_t, cs: &[String], mut o: Option<&mut String>) -> u8 {
+    cs.iter()
+        .any(|c| mpc_input_string(i, c, o.as_deref_mut()) == 1) as u8
+}
+
+// Reads ahead the word at the current position without consuming it.
+fn mpc_input_word(i: &mut mpc_input_t, pos: i32) -> String {
+    mpc_input_with_bytes(i, pos, pos + MPC_WORD_MAX as i32, |b| {
+        String::from_utf8_lossy(b)
+            .chars()
+            .take_while(|c| mpc_is_word(*c))
+            .collect()
+    })
+}
+
+fn mpc_input_anchor(
+    i: &mut mpc_input_t,
+    f: fn(Option<char>, Option<char>) -> bool,
+    _o: Option<&mut String>,
+) -> u8 {
+    let prev = (i.state.pos > 0).then_some(i.last);
+    let next = match mpc_input_decode(i) {
+        mpc_decoded_t::CHAR(c) => Some(c),
+        mpc_decoded_t::END => None,
+        mpc_decoded_t::INVALID => return 0,
+    };
+    f(prev, next) as u8
 }
 
-// Error Type
-struct mpc_err_t {
-    state: mpc_state_t,
-    expected_num: i32,
-    filename: String,
-    failure: String,
-    expected: Vec<String>,
-    received: char,
+// Byte Level Input
+
+fn mpc_input_peekb(i: &mut mpc_input_t) -> Option<u8> {
+    if i.itype == MPC_INPUT_STRING {
+        (*i.string).as_ref().get(i.state.pos as usize).copied()
+    } else if i.itype == MPC_INPUT_PIPE {
+        mpc_input_buffer_fill(i, 1).first().copied()
+    } else {
+        let b = mpc_input_read_byte(i)?;
+        mpc_input_unread(i, &[b]);
+        Some(b)
+    }
 }
 
-// Related Functions
-fn mpc_err_delete(e: &mpc_err_t) {}
-fn mpc_err_string(e: &mpc_err_t) -> String {
-    unimplemented!()
+fn mpc_input_getb(i: &mut mpc_input_t) -> Option<u8> {
+    if i.itype != MPC_INPUT_FILE {
+        return mpc_input_peekb(i);
+    }
+    mpc_input_read_byte(i)
 }
-fn mpc_err_print(e: &mpc_err_t) {}
-fn mpc_err_print_to(e: &mpc_err_t, f: &mut std::fs::File) {}
-
-// Parsing
-type mpc_val_t = c_void;
-type mpc_result_t = Result<Box<mpc_val_t>, mpc_err_t>;
 
-struct mpc_parser_t;
-
-// Related Functions
-fn mpc_parse(filename: &str, string: &str, p: &mpc_parser_t, r: &mpc_result_t) -> i32 {
-    unimplemented!()
+fn mpc_input_byte_failure(i: &mut mpc_input_t, b: u8) -> u8 {
+    mpc_input_unread(i, &[b]);
+    0
 }
 
-fn mpc_nparse(
-    filename: &str,
-    string: &str,
-    length: usize,
-    p: &mpc_parser_t,
-    r: &mpc_result_t,
-) -> i32 {
-    unimplemented!()
-}
+fn mpc_input_byte_success(i: &mut mpc_input_t, b: u8) -> u8 {
+    i.last = b as char;
+    i.state.pos += 1;
 
-fn mpc_parse_file(filename: &str, file: &mut File, p: &mpc_parser_t, r: &mpc_result_t) -> i32 {
-    unimplemented!()
-}
-fn mpc_parse_pipe(filename: &str, pipe: &mut File, p: &mpc_parser_t, r: &mpc_result_t) -> i32 {
-    unimplemented!()
+    mpc_input_buffer_release(i);
+    1
 }
 
-fn mpc_parse_contents(filename: &str, p: &mpc_parser_t, r: &mpc_result_t) -> i32 {
-    unimplemented!()
+fn mpc_input_any_byte(i: &mut mpc_input_t) -> u8 {
+    match mpc_input_getb(i) {
+        Some(b) => mpc_input_byte_success(i, b),
+        None => 0,
+    }
 }
 
-// Function Types
-
-type mpc_dtor_t = fn(&mpc_val_t);
-type mpc_ctor_t = fn() -> mpc_val_t;
-
-type mpc_apply_t = fn(&mpc_val_t) -> mpc_val_t;
-type mpc_apply_to_t = fn(&mpc_val_t, &c_void) -> mpc_val_t;
-type mpc_fold_t = fn(&&mpc_val_t, isize) -> mpc_val_t;
-
-type mpc_check_t = fn(&&mpc_val_t) -> isize;
-type mpc_check_with_t = fn(&&mpc_val_t, &c_void) -> isize;
-
-// Building a Parser
-fn mpc_new(name: &str) -> mpc_parser_t {
-    unimplemented!()
+fn mpc_input_byte_range(i: &mut mpc_input_t, c: u8, d: u8) -> u8 {
+    match mpc_input_getb(i) {
+        Some(b) if b >= c && b <= d => mpc_input_byte_success(i, b),
+        Some(b) => mpc_input_byte_failure(i, b),
+        None => 0,
+    }
 }
 
-fn mpc_copy(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
-}
+// Error Type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum mpc_severity_t {
+    mpc_severity_error,
+    mpc_severity_warning,
+}
+
+// A secondary span pointed to by an error, such as where a bracket was opened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct mpc_label_t {
+    pub span: mpc_span_t,
+    pub message: String,
+}
+
+// What an error can carry besides what was expected. Unset fields are left
+// alone when attached to an error which already has some.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct mpc_diag_t {
+    pub code: Option<String>,
+    pub severity: Option<mpc_severity_t>,
+    pub labels: Vec<mpc_label_t>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+// What went wrong: something else was expected, or a failure message. Left
+// recursion with no base case is a failure too, but one which loses to any
+// other error at the same position.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq)]
+enum mpc_err_kind_t {
+    EXPECTED(Vec<String>),
+    FAILURE(Box<str>),
+    RECURSION(Box<str>),
+}
+
+// `mode` is what the columns of the error and its labels count. `line` is the
+// source line the error is on, if the input still had it, and `line_pos` the
+// byte offset of the error within it. `literals` are those of the alternatives
+// which failed on a word, for suggesting words once the error is returned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct mpc_err_t {
+    state: mpc_state_t,
+    filename: Box<str>,
+    kind: mpc_err_kind_t,
+    received: Option<char>,
+    end_col: i32,
+    mode: mpc_col_mode_t,
+    line_pos: i32,
+    line: Option<Box<str>>,
+    diag: Option<Box<mpc_diag_t>>,
+    literals: Box<[String]>,
+}
+
+fn mpc_err_new(i: &mut mpc_input_t, expected: &str) -> mpc_err_t {
+    let received = match mpc_input_decode(i) {
+        mpc_decoded_t::CHAR(c) => Some(c),
+        mpc_decoded_t::END => None,
+        mpc_decoded_t::INVALID => return mpc_err_fail(i, "Invalid UTF-8 in input!"),
+    };
 
-fn mpc_define(p: &mpc_parser_t, a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+    mpc_err_t {
+        state: i.state,
+        filename: i.filename.as_str().into(),
+        kind: mpc_err_kind_t::EXPECTED(vec![expected.to_owned()]),
+        received,
+        end_col: 0,
+        mode: mpc_col_mode_t::mpc_col_chars,
+        line_pos: 0,
+        line: None,
+        diag: None,
+        literals: Box::new([]),
+    }
 }
 
-fn mpc_undefine(p: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+fn mpc_err_fail(i: &mut mpc_input_t, failure: &str) -> mpc_err_t {
+    mpc_input_decode(i);
+    mpc_err_t {
+        state: i.state,
+        filename: i.filename.as_str().into(),
+        kind: mpc_err_kind_t::FAILURE(failure.into()),
+        received: None,
+        end_col: 0,
+        mode: mpc_col_mode_t::mpc_col_chars,
+        line_pos: 0,
+        line: None,
+        diag: None,
+        literals: Box::new([]),
+    }
 }
 
-fn mpc_delete(p: &mpc_parser_t) {}
-fn mpc_cleanup(n: isize, args: Arguments) {}
-
-// Basic Parsers
-fn mpc_any() -> mpc_parser_t {
-    unimplemented!()
+fn mpc_err_file(filename: &str, failure: &str) -> mpc_err_t {
+    mpc_err_t {
+        state: mpc_state_new(),
+        filename: filename.into(),
+        kind: mpc_err_kind_t::FAILURE(failure.into()),
+        received: None,
+        end_col: 0,
+        mode: mpc_col_mode_t::mpc_col_chars,
+        line_pos: 0,
+        line: None,
+        diag: None,
+        literals: Box::new([]),
+    }
 }
 
-fn mpc_char(c: char) -> mpc_parser_t {
-    unimplemented!()
+// Left recursion called back before it has a seed expects nothing there, so
+// it loses to any other error at the same position. Left alone it can only
+// mean the recursion has no other way to start.
+fn mpc_err_recursion(i: &mut mpc_input_t, name: &str) -> mpc_err_t {
+    let failure = format!("Left recursion in {name} has no base case!");
+    mpc_err_t {
+        kind: mpc_err_kind_t::RECURSION(failure.into()),
+        ..mpc_err_fail(i, "")
+    }
 }
 
-fn mpc_range(s: char, e: char) -> mpc_parser_t {
-    unimplemented!()
-}
+// Errors only carry an offset while parsing, the one which is finally
+// returned is given its row, column and source line.
+fn mpc_err_locate(i: &mut mpc_input_t, mut x: mpc_err_t) -> mpc_err_t {
+    let term = x.state.term;
+    x.state = mpc_input_state_at(i, x.state.pos);
+    x.state.term = term;
+    let (_, start) = mpc_input_line_start(i, x.state.pos);
+    x.end_col = match x.received {
+        Some(c) => mpc_input_col(i, start, x.state.pos + c.len_utf8() as i32),
+        None => x.state.col,
+    };
+    x.mode = i.lines.mode;
+    x.line_pos = x.state.pos - start;
+    x.line = mpc_input_line(i, x.state.row).map(Into::into);
+    x
+}
+
+// Of two failed alternatives the one which got furthest wins, at the same
+// position what both expected is merged. A failure message, having no
+// expected set to merge, takes precedence.
+fn mpc_err_or(x: mpc_err_t, y: mpc_err_t) -> mpc_err_t {
+    let recursion = |e: &mpc_err_t| matches!(e.kind, mpc_err_kind_t::RECURSION(_));
+    if y.state.pos > x.state.pos || (y.state.pos == x.state.pos && recursion(&x)) {
+        return y;
+    }
+    if y.state.pos < x.state.pos || recursion(&y) {
+        return x;
+    }
 
-fn mpc_oneof(s: &str) -> mpc_parser_t {
-    unimplemented!()
-}
+    if !matches!(x.kind, mpc_err_kind_t::EXPECTED(_)) {
+        return x;
+    }
+    if !matches!(y.kind, mpc_err_kind_t::EXPECTED(_)) {
+        return y;
+    }
 
-fn mpc_noneof(s: &str) -> mpc_parser_t {
-    unimplemented!()
+    let mut e = x;
+    if let (mpc_err_kind_t::EXPECTED(xs), mpc_err_kind_t::EXPECTED(ys)) = (&mut e.kind, y.kind) {
+        xs.extend(ys);
+        xs.sort();
+        xs.dedup();
+    }
+    if e.diag.is_none() {
+        e.diag = y.diag;
+    }
+    mpc_err_literals(&mut e, y.literals.into_vec());
+    e
 }
 
-fn mpc_satisfy(f: fn(char) -> i32) -> mpc_parser_t {
-    unimplemented!()
+// Joins as in "a, b or c".
+fn mpc_err_list(xs: &[String]) -> String {
+    match xs {
+        [] => String::new(),
+        [x] => x.clone(),
+        [xs @ .., x] => format!("{} or {x}", xs.join(", ")),
+    }
 }
 
-fn mpc_string(s: &str) -> mpc_parser_t {
-    unimplemented!()
+fn mpc_err_repeat(mut x: mpc_err_t, prefix: &str) -> mpc_err_t {
+    if let mpc_err_kind_t::EXPECTED(xs) = &mut x.kind {
+        *xs = vec![format!("{prefix}{}", mpc_err_list(xs))];
+    }
+    x
 }
 
-// Other Parsers
-
-fn mpc_pass() -> mpc_parser_t {
-    unimplemented!()
+fn mpc_err_many1(x: mpc_err_t) -> mpc_err_t {
+    mpc_err_repeat(x, "one or more of ")
 }
 
-fn mpc_fail(m: &str) -> mpc_parser_t {
-    unimplemented!()
+fn mpc_err_count(x: mpc_err_t, n: usize) -> mpc_err_t {
+    mpc_err_repeat(x, &format!("{n} of "))
 }
 
-fn mpc_failf(fmt: &str, args: Arguments) -> mpc_parser_t {
-    unimplemented!()
+// Optimal string alignment distance, so a swapped pair of letters counts once.
+fn mpc_edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (k, row) in d.iter_mut().enumerate() {
+        row[0] = k;
+    }
+    d[0] = (0..=b.len()).collect();
+    for k in 1..=a.len() {
+        for l in 1..=b.len() {
+            let cost = (a[k - 1] != b[l - 1]) as usize;
+            d[k][l] = (d[k - 1][l] + 1)
+                .min(d[k][l - 1] + 1)
+                .min(d[k - 1][l - 1] + cost);
+            if k > 1 && l > 1 && a[k - 1] == b[l - 2] && a[k - 2] == b[l - 1] {
+                d[k][l] = d[k][l].min(d[k - 2][l - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
 }
 
-fn mpc_lift(f: mpc_ctor_t) -> mpc_parser_t {
-    unimplemented!()
+// The literals the alternatives of `p` start with, looking through the
+// tokenizing and labelling wrappers put around them.
+fn mpc_literals(p: &mpc_parser_t, depth: usize, out: &mut Vec<String>) {
+    if depth == 0 {
+        return;
+    }
+    match &p.kind {
+        mpc_type_t::STRING(s) | mpc_type_t::STRING_NOCASE(s) => out.push(s.clone()),
+        mpc_type_t::STRING_ONEOF(ss) => out.extend(ss.iter().cloned()),
+        mpc_type_t::NAMED(d, _) => {
+            if let Some(d) = &*d.borrow() {
+                mpc_literals(d, depth - 1, out);
+            }
+        }
+        mpc_type_t::OR => {
+            for c in &p.children {
+                mpc_literals(c, depth - 1, out);
+            }
+        }
+        mpc_type_t::AND(_)
+        | mpc_type_t::APPLY(_)
+        | mpc_type_t::CHECK(..)
+        | mpc_type_t::EXPECT(..)
+        | mpc_type_t::RECOVER
+        | mpc_type_t::PREDICT => {
+            if let Some(c) = p.children.first() {
+                mpc_literals(c, depth - 1, out);
+            }
+        }
+        _ => {}
+    }
 }
 
-fn mpc_lift_val(x: Box<mpc_val_t>) -> mpc_parser_t {
-    unimplemented!()
+fn mpc_err_literals(e: &mut mpc_err_t, ls: Vec<String>) {
+    if ls.is_empty() {
+        return;
+    }
+    let mut xs = std::mem::take(&mut e.literals).into_vec();
+    xs.extend(ls);
+    xs.sort();
+    xs.dedup();
+    e.literals = xs.into();
+}
+
+// When none of the alternatives of `p` matched a word, notes the literals
+// among them so that those closest to the word can be suggested should the
+// error be returned. Alternatives enclosing others which failed at the same
+// place add theirs too. Errors already carrying help are left alone.
+fn mpc_err_alternatives(p: &mpc_parser_t, mut e: mpc_err_t) -> mpc_err_t {
+    if !matches!(e.kind, mpc_err_kind_t::EXPECTED(_))
+        || !e.received.is_some_and(mpc_is_word)
+        || !mpc_err_help(&e).is_empty()
+    {
+        return e;
+    }
+    let mut ls = vec![];
+    mpc_literals(p, 8, &mut ls);
+    mpc_err_literals(&mut e, ls);
+    e
 }
 
-fn mpc_anchor(f: fn(char, char) -> i32) -> mpc_parser_t {
-    unimplemented!()
-}
+fn mpc_err_suggest(i: &mut mpc_input_t, mut e: mpc_err_t) -> mpc_err_t {
+    let ls = std::mem::take(&mut e.literals);
+    if ls.is_empty() {
+        return e;
+    }
 
-fn mpc_state() -> mpc_state_t {
-    unimplemented!()
+    let w = mpc_input_word(i, e.state.pos);
+    let mut best = usize::MAX;
+    let mut xs = vec![];
+    for l in ls.iter() {
+        let n = l.chars().count();
+        let d = mpc_edit_distance(&w, l);
+        if d == 0 || d > (n / 3).max(1) || d >= n || d > best {
+            continue;
+        }
+        if d < best {
+            best = d;
+            xs.clear();
+        }
+        xs.push(format!("`{l}`"));
+    }
+    xs.sort();
+    xs.dedup();
+
+    if !xs.is_empty() {
+        let help = vec![format!("did you mean {}?", mpc_err_list(&xs))];
+        mpc_err_annotate(
+            &mut e,
+            mpc_diag_t {
+                help,
+                ..Default::default()
+            },
+        );
+    }
+    e
 }
 
-// Combinator Parsers
-fn mpc_expect(a: &mpc_parser_t, e: &str) -> mpc_parser_t {
-    unimplemented!()
+// Related Functions
+pub fn mpc_err_delete(e: mpc_err_t) {
+    drop(e)
 }
-
-fn mpc_expectf(a: &mpc_parser_t, fmt: &str, args: Arguments) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_string(e: &mpc_err_t) -> String {
+    mpc_err_render(e, false)
 }
-
-fn mpc_apply(a: &mpc_parser_t, f: mpc_apply_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_string_color(e: &mpc_err_t) -> String {
+    mpc_err_render(e, true)
 }
-
-fn mpc_apply_to(a: &mpc_parser_t, f: mpc_apply_to_t, x: Box<c_void>) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_print(e: &mpc_err_t) {
+    let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    print!("{}", mpc_err_render(e, color));
 }
-
-fn mpc_check(a: &mpc_parser_t, da: mpc_dtor_t, f: mpc_check_t, e: &str) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_print_to<W: Write + ?Sized>(e: &mpc_err_t, f: &mut W) -> std::io::Result<()> {
+    f.write_all(mpc_err_string(e).as_bytes())
 }
 
-fn mpc_check_with(
-    a: &mpc_parser_t,
-    da: mpc_dtor_t,
-    f: mpc_check_with_t,
-    x: Box<c_void>,
-    e: &str,
-) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_state(e: &mpc_err_t) -> mpc_state_t {
+    e.state
 }
-
-fn mpc_checkf(
-    a: &mpc_parser_t,
-    da: mpc_dtor_t,
-    f: mpc_check_t,
-    fmt: &str,
-    args: Arguments,
-) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_filename(e: &mpc_err_t) -> &str {
+    &e.filename
 }
-
-fn mpc_check_withf(
-    a: &mpc_parser_t,
-    da: mpc_dtor_t,
-    f: mpc_check_with_t,
-    x: Box<c_void>,
-    fmt: &str,
-    args: Arguments,
-) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_expected(e: &mpc_err_t) -> &[String] {
+    match &e.kind {
+        mpc_err_kind_t::EXPECTED(xs) => xs,
+        _ => &[],
+    }
 }
-
-fn mpc_not(a: &mpc_parser_t, da: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
+// `None` at the end of the input.
+pub fn mpc_err_received(e: &mpc_err_t) -> Option<char> {
+    e.received
 }
-
-fn mpc_not_lift(a: &mpc_parser_t, da: mpc_dtor_t, lf: mpc_ctor_t) -> mpc_parser_t {
-    unimplemented!()
+// Only errors from `mpc_fail` and friends have a failure message rather than
+// an expected set.
+pub fn mpc_err_failure(e: &mpc_err_t) -> Option<&str> {
+    match &e.kind {
+        mpc_err_kind_t::EXPECTED(_) => None,
+        mpc_err_kind_t::FAILURE(x) | mpc_err_kind_t::RECURSION(x) => Some(x),
+    }
 }
-
-fn mpc_maybe(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_line(e: &mpc_err_t) -> Option<&str> {
+    e.line.as_deref()
 }
-
-fn mpc_maybe_lift(a: &mpc_parser_t, lf: mpc_ctor_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_code(e: &mpc_err_t) -> Option<&str> {
+    e.diag.as_ref().and_then(|d| d.code.as_deref())
 }
-
-fn mpc_many(f: mpc_fold_t, a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_severity(e: &mpc_err_t) -> mpc_severity_t {
+    e.diag
+        .as_ref()
+        .and_then(|d| d.severity)
+        .unwrap_or(mpc_severity_t::mpc_severity_error)
 }
-
-fn mpc_many1(f: mpc_fold_t, a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_labels(e: &mpc_err_t) -> &[mpc_label_t] {
+    e.diag.as_ref().map_or(&[], |d| &d.labels)
 }
-
-fn mpc_count(n: i32, f: mpc_fold_t, a: &mpc_parser_t, da: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_notes(e: &mpc_err_t) -> &[String] {
+    e.diag.as_ref().map_or(&[], |d| &d.notes)
 }
-
-fn mpc_or(n: i32, args: Arguments) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_help(e: &mpc_err_t) -> &[String] {
+    e.diag.as_ref().map_or(&[], |d| &d.help)
 }
 
-fn mpc_and(n: i32, f: mpc_fold_t, args: Arguments) -> mpc_parser_t {
-    unimplemented!()
+// Code and severity are replaced if `d` sets them, everything else is added.
+pub fn mpc_err_annotate(e: &mut mpc_err_t, d: mpc_diag_t) {
+    let x = e.diag.get_or_insert_with(Default::default);
+    if d.code.is_some() {
+        x.code = d.code;
+    }
+    if d.severity.is_some() {
+        x.severity = d.severity;
+    }
+    x.labels.extend(d.labels);
+    x.notes.extend(d.notes);
+    x.help.extend(d.help);
 }
 
-fn mpc_predictive(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+fn mpc_err_diag(mut e: mpc_err_t, d: &Option<Rc<mpc_diag_t>>) -> mpc_err_t {
+    if let Some(d) = d {
+        mpc_err_annotate(&mut e, mpc_diag_t::clone(d));
+    }
+    e
 }
 
-// Common Parsers
-
-fn mpc_eoi() -> mpc_parser_t {
-    unimplemented!()
+// Just the message, `{:#}` adds the source excerpt.
+impl std::fmt::Display for mpc_err_t {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = mpc_err_render(self, false);
+        if f.alternate() {
+            f.write_str(s.trim_end())
+        } else {
+            f.write_str(s.lines().next().unwrap_or_default())
+        }
+    }
 }
 
-fn mpc_soi() -> mpc_parser_t {
-    unimplemented!()
+impl std::error::Error for mpc_err_t {}
+
+fn mpc_err_char_unescape(c: Option<char>) -> String {
+    match c {
+        None => "end of input".to_owned(),
+        Some('\x07') => "bell".to_owned(),
+        Some('\x08') => "backspace".to_owned(),
+        Some('\x0c') => "formfeed".to_owned(),
+        Some('\r') => "carriage return".to_owned(),
+        Some('\x0b') => "vertical tab".to_owned(),
+        Some('\0') => "null character".to_owned(),
+        Some('\n') => "newline".to_owned(),
+        Some('\t') => "tab".to_owned(),
+        Some(' ') => "space".to_owned(),
+        Some(c) => format!("'{c}'"),
+    }
 }
 
-fn mpc_boundary() -> mpc_parser_t {
-    unimplemented!()
+// Labels quote what was expected as it is, so control characters in them are
+// escaped to keep the message on one line.
+fn mpc_err_label(x: &str) -> String {
+    x.chars()
+        .map(|c| match MPC_ESCAPE_INPUT_C.iter().position(|&e| e == c) {
+            Some(k) if c.is_control() => MPC_ESCAPE_OUTPUT_C[k].to_owned(),
+            _ if c.is_control() => format!("\\x{:02x}", c as u32),
+            _ => c.to_string(),
+        })
+        .collect()
 }
 
-fn mpc_boundary_newline() -> mpc_parser_t {
-    unimplemented!()
+// The message without location, as in "expected 'a' at 'b'".
+fn mpc_err_message(e: &mpc_err_t) -> String {
+    let xs = match &e.kind {
+        mpc_err_kind_t::EXPECTED(xs) => xs,
+        mpc_err_kind_t::FAILURE(x) | mpc_err_kind_t::RECURSION(x) => return x.to_string(),
+    };
+    let prefix = if xs.len() > 1 { "one of " } else { "" };
+    let expected: Vec<String> = xs.iter().map(|x| mpc_err_label(x)).collect();
+    format!(
+        "expected {prefix}{} at {}",
+        mpc_err_list(&expected),
+        mpc_err_char_unescape(e.received)
+    )
+}
+
+fn mpc_err_level(e: &mpc_err_t) -> &'static str {
+    match mpc_err_severity(e) {
+        mpc_severity_t::mpc_severity_error => "error",
+        mpc_severity_t::mpc_severity_warning => "warning",
+    }
 }
 
-fn mpc_whitespace() -> mpc_parser_t {
-    unimplemented!()
-}
+// In the style of rustc: the message, then the source line with a caret
+// under where things went wrong. Tabs are shown as four spaces.
+fn mpc_err_render(e: &mpc_err_t, color: bool) -> String {
+    let paint = |code: &str, s: &str| {
+        if color {
+            format!("\x1b[{code}m{s}\x1b[0m")
+        } else {
+            s.to_owned()
+        }
+    };
 
-fn mpc_whitespaces() -> mpc_parser_t {
-    unimplemented!()
-}
+    let level = mpc_err_level(e);
+    let code = match mpc_err_severity(e) {
+        mpc_severity_t::mpc_severity_error => "1;31",
+        mpc_severity_t::mpc_severity_warning => "1;33",
+    };
+    let level = match mpc_err_code(e) {
+        Some(c) => format!("{level}[{c}]:"),
+        None => format!("{level}:"),
+    };
 
-fn mpc_blank() -> mpc_parser_t {
-    unimplemented!()
-}
+    let mut out = format!(
+        "{}:{}:{}: {} ",
+        e.filename,
+        e.state.row + 1,
+        e.state.col + 1,
+        paint(code, &level)
+    );
+
+    out.push_str(&mpc_err_message(e));
+    out.push('\n');
+
+    let row = (e.state.row + 1).to_string();
+    let pad = " ".repeat(row.len());
+    let bar = paint("1;34", "|");
+
+    // Labels on the same line are underlined beneath it, others just located.
+    let mut others = vec![];
+    if let Some(line) = &e.line {
+        let width = |s: &str| {
+            s.chars()
+                .map(|c| if c == '\t' { 4 } else { c.width().unwrap_or(0) })
+                .sum::<usize>()
+        };
+        let floor = |k: i32| {
+            let mut k = (k.max(0) as usize).min(line.len());
+            while !line.is_char_boundary(k) {
+                k -= 1;
+            }
+            k
+        };
+        let k = floor(e.line_pos);
+        let start = e.state.pos - e.line_pos;
+
+        out.push_str(&format!("{pad} {bar}\n"));
+        out.push_str(&format!(
+            "{} {bar} {}\n",
+            paint("1;34", &row),
+            line.replace('\t', "    ")
+        ));
+        out.push_str(&format!(
+            "{pad} {bar} {}{}\n",
+            " ".repeat(width(&line[..k])),
+            paint(code, "^")
+        ));
+
+        for l in mpc_err_labels(e) {
+            if l.span.start.row != e.state.row {
+                others.push(l);
+                continue;
+            }
+            let a = floor(l.span.start.pos - start);
+            let b = floor(l.span.end.pos - start).max(a);
+            let n = width(&line[a..b]).max(1);
+            out.push_str(&format!(
+                "{pad} {bar} {}{}\n",
+                " ".repeat(width(&line[..a])),
+                paint("1;34", &format!("{} {}", "-".repeat(n), l.message))
+            ));
+        }
+    } else {
+        others.extend(mpc_err_labels(e));
+    }
 
-fn mpc_digit() -> mpc_parser_t {
-    unimplemented!()
+    for l in others {
+        out.push_str(&format!(
+            "{pad} {} {}:{}:{}: {}\n",
+            paint("1;34", ":::"),
+            e.filename,
+            l.span.start.row + 1,
+            l.span.start.col + 1,
+            l.message
+        ));
+    }
+    for n in mpc_err_notes(e) {
+        out.push_str(&format!("{pad} {} note: {n}\n", paint("1;34", "=")));
+    }
+    for h in mpc_err_help(e) {
+        out.push_str(&format!("{pad} {} help: {h}\n", paint("1;34", "=")));
+    }
+    out
 }
 
-fn mpc_hexdigit() -> mpc_parser_t {
-    unimplemented!()
+// Serialization
+// Errors span the character received, or nothing at the end of the input.
+// Lines and columns are counted from one, offsets are in bytes.
+fn mpc_err_end(e: &mpc_err_t) -> mpc_state_t {
+    let mut end = e.state;
+    if let Some(c) = e.received {
+        end.pos += c.len_utf8() as i32;
+        end.col = e.end_col;
+    }
+    end
+}
+
+fn mpc_json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
 }
 
-fn mpc_octdigit() -> mpc_parser_t {
-    unimplemented!()
+fn mpc_json_opt(s: Option<&str>) -> String {
+    s.map_or_else(|| "null".to_owned(), mpc_json_string)
 }
 
-fn mpc_digits() -> mpc_parser_t {
-    unimplemented!()
+fn mpc_json_array(xs: impl Iterator<Item = String>) -> String {
+    format!("[{}]", xs.collect::<Vec<_>>().join(","))
 }
 
-fn mpc_hexdigits() -> mpc_parser_t {
-    unimplemented!()
+fn mpc_json_state(s: mpc_state_t) -> String {
+    format!(
+        "{{\"offset\":{},\"line\":{},\"column\":{}}}",
+        s.pos,
+        s.row + 1,
+        s.col + 1
+    )
 }
 
-fn mpc_octdigits() -> mpc_parser_t {
-    unimplemented!()
+fn mpc_json_strings(xs: &[String]) -> String {
+    mpc_json_array(xs.iter().map(|x| mpc_json_string(x)))
 }
 
-fn mpc_lower() -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_upper() -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_alpha() -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_underscore() -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_alphanum() -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_err_json(e: &mpc_err_t) -> String {
+    let received = e.received.map(String::from);
+    let labels = mpc_err_labels(e).iter().map(|l| {
+        format!(
+            "{{\"start\":{},\"end\":{},\"message\":{}}}",
+            mpc_json_state(l.span.start),
+            mpc_json_state(l.span.end),
+            mpc_json_string(&l.message)
+        )
+    });
+    format!(
+        "{{\"filename\":{},\"start\":{},\"end\":{},\"code\":{},\"severity\":{},\"expected\":{},\"received\":{},\"failure\":{},\"message\":{},\"labels\":{},\"notes\":{},\"help\":{}}}",
+        mpc_json_string(&e.filename),
+        mpc_json_state(e.state),
+        mpc_json_state(mpc_err_end(e)),
+        mpc_json_opt(mpc_err_code(e)),
+        mpc_json_string(mpc_err_level(e)),
+        mpc_json_strings(mpc_err_expected(e)),
+        mpc_json_opt(received.as_deref()),
+        mpc_json_opt(mpc_err_failure(e)),
+        mpc_json_string(&mpc_err_message(e)),
+        mpc_json_array(labels),
+        mpc_json_strings(mpc_err_notes(e)),
+        mpc_json_strings(mpc_err_help(e))
+    )
 }
 
-fn mpc_int() -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_hex() -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_oct() -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_number() -> mpc_parser_t {
-    unimplemented!()
+pub fn mpc_errs_json(es: &[mpc_err_t]) -> String {
+    mpc_json_array(es.iter().map(mpc_err_json))
 }
 
-fn mpc_real() -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_float() -> mpc_parser_t {
-    unimplemented!()
+// Errors without a code of their own fall under a generic rule.
+fn mpc_err_sarif_rule(e: &mpc_err_t) -> &str {
+    mpc_err_code(e).unwrap_or("parse-error")
 }
 
-fn mpc_char_lit() -> mpc_parser_t {
-    unimplemented!()
+// SARIF counts columns in characters. Other modes can only be converted back
+// on the error's own line, elsewhere the column is left out.
+fn mpc_err_sarif_col(e: &mpc_err_t, s: mpc_state_t) -> Option<i32> {
+    if e.mode == mpc_col_mode_t::mpc_col_chars {
+        return Some(s.col);
+    }
+    let line = e.line.as_deref().filter(|_| s.row == e.state.row)?;
+    let k = s.pos - (e.state.pos - e.line_pos);
+    let head = line.get(..usize::try_from(k).ok()?)?;
+    Some(head.chars().count() as i32)
+}
+
+// The `physicalLocation` member of a location, `extra` going in its region.
+fn mpc_sarif_location(e: &mpc_err_t, start: mpc_state_t, end: mpc_state_t, extra: &str) -> String {
+    let (a, b) = match (mpc_err_sarif_col(e, start), mpc_err_sarif_col(e, end)) {
+        (Some(a), Some(b)) => (
+            format!(",\"startColumn\":{}", a + 1),
+            format!(",\"endColumn\":{}", b + 1),
+        ),
+        _ => (String::new(), String::new()),
+    };
+    format!(
+        "\"physicalLocation\":{{\"artifactLocation\":{{\"uri\":{}}},\"region\":{{\"startLine\":{}{a},\"endLine\":{}{b},\"byteOffset\":{},\"byteLength\":{}{extra}}}}}",
+        mpc_json_string(&e.filename),
+        start.row + 1,
+        end.row + 1,
+        start.pos,
+        end.pos - start.pos
+    )
+}
+
+// Notes and help are given as further lines of the message.
+fn mpc_err_sarif_result(e: &mpc_err_t) -> String {
+    let snippet = match mpc_err_line(e) {
+        Some(l) => format!(",\"snippet\":{{\"text\":{}}}", mpc_json_string(l)),
+        None => String::new(),
+    };
+    let mut message = mpc_err_message(e);
+    for n in mpc_err_notes(e) {
+        message.push_str(&format!("\nnote: {n}"));
+    }
+    for h in mpc_err_help(e) {
+        message.push_str(&format!("\nhelp: {h}"));
+    }
+    let related = mpc_err_labels(e).iter().enumerate().map(|(k, l)| {
+        let location = mpc_sarif_location(e, l.span.start, l.span.end, "");
+        format!(
+            "{{\"id\":{k},\"message\":{{\"text\":{}}},{location}}}",
+            mpc_json_string(&l.message)
+        )
+    });
+    format!(
+        "{{\"ruleId\":{},\"level\":{},\"message\":{{\"text\":{}}},\"locations\":[{{{}}}],\"relatedLocations\":{}}}",
+        mpc_json_string(mpc_err_sarif_rule(e)),
+        mpc_json_string(mpc_err_level(e)),
+        mpc_json_string(&message),
+        mpc_sarif_location(e, e.state, mpc_err_end(e), &snippet),
+        mpc_json_array(related)
+    )
+}
+
+// A SARIF 2.1.0 log with a single run holding one result per error. Columns
+// count characters whatever the input counted, as the log declares.
+pub fn mpc_errs_sarif(es: &[mpc_err_t]) -> String {
+    let mut rules: Vec<&str> = es.iter().map(mpc_err_sarif_rule).collect();
+    rules.sort();
+    rules.dedup();
+    let rules = rules
+        .into_iter()
+        .map(|r| format!("{{\"id\":{}}}", mpc_json_string(r)));
+    format!(
+        "{{\"$schema\":\"https://json.schemastore.org/sarif-2.1.0.json\",\"version\":\"2.1.0\",\"runs\":[{{\"tool\":{{\"driver\":{{\"name\":\"mpc\",\"rules\":{}}}}},\"columnKind\":\"unicodeCodePoints\",\"results\":{}}}]}}",
+        mpc_json_array(rules),
+        mpc_json_array(es.iter().map(mpc_err_sarif_result))
+    )
+}
+
+pub fn mpc_err_sarif(e: &mpc_err_t) -> String {
+    mpc_errs_sarif(std::slice::from_ref(e))
 }
 
-fn mpc_string_lit() -> mpc_parser_t {
-    unimplemented!()
+// Parsing
+// A value of any type, stored in a block of the input's pool or on the heap.
+pub struct mpc_val_t {
+    ptr: *mut u8,
+    type_id: TypeId,
+    drop: unsafe fn(*mut u8, bool),
+    pool: Option<(Rc<mpc_pool_t>, usize)>,
+}
+
+// Drops the value behind `p`, freeing it as well if it lives on the heap.
+unsafe fn mpc_val_drop<O>(p: *mut u8, heap: bool) {
+    if heap {
+        drop(Box::from_raw(p as *mut O));
+    } else {
+        ptr::drop_in_place(p as *mut O);
+    }
 }
 
-fn mpc_regex_lit() -> mpc_parser_t {
-    unimplemented!()
+impl Drop for mpc_val_t {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.ptr, self.pool.is_none()) };
+        if let Some((m, k)) = &self.pool {
+            mpc_mem_free(m, *k);
+        }
+    }
 }
 
-// Useful Parsers
-
-fn mpc_startwith(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+pub type mpc_result_t<O = mpc_val_t> = Result<O, mpc_err_t>;
+
+// The graph itself is untyped, callbacks are stored type-erased and only
+// ever see the values produced by the children `Parser<O>` built them from.
+pub type mpc_any_ctor_t = Rc<dyn Fn(&mpc_input_t) -> mpc_val_t>;
+pub type mpc_any_apply_t = Rc<dyn Fn(&mpc_input_t, mpc_val_t) -> mpc_val_t>;
+pub type mpc_any_check_t = Rc<dyn Fn(&mpc_val_t) -> bool>;
+pub type mpc_any_fold_t = Rc<dyn Fn(&mpc_input_t, Vec<mpc_val_t>) -> mpc_val_t>;
+pub type mpc_any_clone_t = fn(&mpc_input_t, &mpc_val_t) -> mpc_val_t;
+
+#[derive(Clone)]
+pub enum mpc_type_t {
+    NAMED(RefCell<Option<Rc<mpc_parser_t>>>, mpc_any_clone_t),
+    PASS,
+    FAIL(String, Option<Rc<mpc_diag_t>>),
+    LIFT(mpc_any_ctor_t),
+    ANCHOR(fn(Option<char>, Option<char>) -> bool),
+    STATE,
+
+    ANY,
+    SINGLE(char),
+    ONEOF(String),
+    NONEOF(String),
+    RANGE(char, char),
+    SATISFY(fn(char) -> bool),
+    STRING(String),
+    STRING_NOCASE(String),
+    STRING_ONEOF(Vec<String>),
+
+    ANY_BYTE,
+    BYTE_RANGE(u8, u8),
+    BLOCK,
+
+    APPLY(mpc_any_apply_t),
+    CHECK(mpc_any_check_t, String, Option<Rc<mpc_diag_t>>),
+    EXPECT(String, Option<Rc<mpc_diag_t>>),
+    RECOVER,
+    PREDICT,
+    SPAN,
+
+    NOT(mpc_any_ctor_t),
+    MAYBE(mpc_any_ctor_t),
+
+    MANY(mpc_any_fold_t),
+    MANY1(mpc_any_fold_t),
+    COUNT(usize, mpc_any_fold_t),
+
+    OR,
+    AND(mpc_any_fold_t),
+}
+
+// `reaches_named` is whether a named parser, and so possibly a cycle, can be
+// reached from the node, and `handles` how many `Parser`s are on it.
+#[derive(Clone)]
+pub struct mpc_parser_t {
+    pub name: Option<String>,
+    pub kind: mpc_type_t,
+    pub children: Vec<Rc<mpc_parser_t>>,
+    pub retained: bool,
+    reaches_named: bool,
+    handles: Cell<usize>,
+}
+
+// A handle on a node of the parser graph which is statically known to
+// produce values of type `O`.
+pub struct Parser<O> {
+    p: Rc<mpc_parser_t>,
+    o: PhantomData<fn() -> O>,
+}
+
+impl<O> Clone for Parser<O> {
+    fn clone(&self) -> Self {
+        mpc_handle(self.p.clone())
+    }
 }
 
-fn mpc_endwith(a: &mpc_parser_t, da: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
+// Only the last handle on a node can leave a cycle unreachable, and then only
+// if the named parsers nearest to it have no handles left either.
+impl<O> Drop for Parser<O> {
+    fn drop(&mut self) {
+        let handles = self.p.handles.get() - 1;
+        self.p.handles.set(handles);
+        if handles == 0 && self.p.reaches_named && mpc_unhandled(&self.p) {
+            mpc_collect(&self.p);
+        }
+    }
 }
 
-fn mpc_whole(a: &mpc_parser_t, da: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
-}
+impl<O> Deref for Parser<O> {
+    type Target = mpc_parser_t;
 
-fn mpc_stripl(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_stripr(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_strip(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_tok(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_sym(s: &str) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_total(a: &mpc_parser_t, da: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
+    fn deref(&self) -> &mpc_parser_t {
+        &self.p
+    }
 }
 
-fn mpc_between(a: &mpc_parser_t, ad: mpc_dtor_t, o: &str, c: &str) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_parens(a: &mpc_parser_t, ad: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_braces(a: &mpc_parser_t, ad: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_brackets(a: &mpc_parser_t, ad: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_squares(a: &mpc_parser_t, ad: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
+fn mpc_handle<O>(p: Rc<mpc_parser_t>) -> Parser<O> {
+    p.handles.set(p.handles.get() + 1);
+    Parser { p, o: PhantomData }
 }
 
-fn mpc_tok_between(a: &mpc_parser_t, ad: mpc_dtor_t, o: &str, c: &str) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_tok_parens(a: &mpc_parser_t, ad: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_tok_braces(a: &mpc_parser_t, ad: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_tok_brackets(a: &mpc_parser_t, ad: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
-}
-fn mpc_tok_squares(a: &mpc_parser_t, ad: mpc_dtor_t) -> mpc_parser_t {
-    unimplemented!()
+fn mpc_node<O>(kind: mpc_type_t, children: Vec<Rc<mpc_parser_t>>) -> Parser<O> {
+    let reaches_named = children.iter().any(|c| c.reaches_named);
+    let p = mpc_parser_t {
+        name: None,
+        kind,
+        children,
+        retained: false,
+        reaches_named,
+        handles: Cell::new(0),
+    };
+    mpc_handle(Rc::new(p))
 }
 
-// Common Function Parameters
-
-fn mpcf_dtor_null(x: Box<mpc_val_t>) {}
-
-fn mpcf_ctor_null() -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_ctor_str() -> mpc_val_t {
-    unimplemented!()
+// The children of `p` along with its definition if it is a named parser.
+fn mpc_edges(p: &mpc_parser_t) -> Vec<Rc<mpc_parser_t>> {
+    let mut cs = p.children.clone();
+    if let mpc_type_t::NAMED(d, _) = &p.kind {
+        cs.extend(d.borrow().clone());
+    }
+    cs
 }
 
-fn mpcf_free(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_int(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_hex(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_oct(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_float(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_strtriml(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_strtrimr(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_strtrim(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
+// Whether `p` is named, or some named parser reached from it without passing
+// another has no handles. Nodes with handles stay reachable, and so does
+// everything they reach.
+fn mpc_unhandled(p: &mpc_parser_t) -> bool {
+    if let mpc_type_t::NAMED(..) = p.kind {
+        return true;
+    }
+    let mut seen = HashSet::new();
+    let mut stack: Vec<&mpc_parser_t> = vec![p];
+    while let Some(q) = stack.pop() {
+        for c in &q.children {
+            if !c.reaches_named || c.handles.get() > 0 || !seen.insert(Rc::as_ptr(c)) {
+                continue;
+            }
+            if let mpc_type_t::NAMED(..) = c.kind {
+                return true;
+            }
+            stack.push(c);
+        }
+    }
+    false
+}
+
+// Named parsers can refer to one another in cycles, which counting references
+// alone would leak. So when a reference to `p` which might have kept a cycle
+// alive is about to go, everything reachable from it is visited and, of that,
+// whatever is no longer reachable from outside has its definitions dropped.
+// `p` itself is assumed to have one reference less than it has.
+fn mpc_collect(p: &Rc<mpc_parser_t>) {
+    let mut index = HashMap::new();
+    let mut nodes = vec![p.clone()];
+    let mut edges = vec![];
+    index.insert(Rc::as_ptr(p), 0);
+
+    let mut k = 0;
+    while k < nodes.len() {
+        let mut es = vec![];
+        for c in mpc_edges(&nodes[k]) {
+            let n = *index.entry(Rc::as_ptr(&c)).or_insert_with(|| {
+                nodes.push(c.clone());
+                nodes.len() - 1
+            });
+            es.push(n);
+        }
+        edges.push(es);
+        k += 1;
+    }
 
-fn mpcf_escape(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_escape_regex(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_escape_string_raw(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_escape_char_raw(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
+    let mut internal = vec![0; nodes.len()];
+    for &n in edges.iter().flatten() {
+        internal[n] += 1;
+    }
 
-fn mpcf_unescape(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_unescape_regex(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_unescape_string_raw(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_unescape_char_raw(x: Box<mpc_val_t>) -> mpc_val_t {
-    unimplemented!()
-}
+    let mut live = vec![false; nodes.len()];
+    let mut stack: Vec<usize> = (0..nodes.len())
+        .filter(|&k| Rc::strong_count(&nodes[k]) > 1 + internal[k] + (k == 0) as usize)
+        .collect();
+    while let Some(k) = stack.pop() {
+        if !live[k] {
+            live[k] = true;
+            stack.extend(&edges[k]);
+        }
+    }
 
-fn mpcf_null(n: i32, xs: Box<Box<mpc_val_t>>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_fst(n: i32, xs: Box<Box<mpc_val_t>>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_snd(n: i32, xs: Box<Box<mpc_val_t>>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_trd(n: i32, xs: Box<Box<mpc_val_t>>) -> mpc_val_t {
-    unimplemented!()
+    let mut dead = vec![];
+    for (k, q) in nodes.iter().enumerate() {
+        if let (false, mpc_type_t::NAMED(d, _)) = (live[k], &q.kind) {
+            dead.extend(d.borrow_mut().take());
+        }
+    }
+    drop(dead);
 }
 
-fn mpcf_all_free(n: i32, xs: Box<Box<mpc_val_t>>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_fst_free(n: i32, xs: Box<Box<mpc_val_t>>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_snd_free(n: i32, xs: Box<Box<mpc_val_t>>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_trd_free(n: i32, xs: Box<Box<mpc_val_t>>) -> mpc_val_t {
-    unimplemented!()
-}
+fn mpc_val_take<O: 'static>(x: mpc_val_t) -> O {
+    if x.type_id != TypeId::of::<O>() {
+        panic!("Error: Parser produced a value of an unexpected type.");
+    }
 
-fn mpcf_freefold(n: i32, xs: Box<Box<mpc_val_t>>) -> mpc_val_t {
-    unimplemented!()
-}
-fn mpcf_strfold(n: i32, xs: Box<Box<mpc_val_t>>) -> mpc_val_t {
-    unimplemented!()
+    let mut x = ManuallyDrop::new(x);
+    let v = unsafe { ptr::read(x.ptr as *mut O) };
+    match x.pool.take() {
+        Some((m, k)) => mpc_mem_free(&m, k),
+        None => drop(unsafe { Box::from_raw(x.ptr as *mut MaybeUninit<O>) }),
+    }
+    v
 }
 
-// Regular Expression Parsers
-
-enum MPC {
-    RE_DEFAULT,
-    RE_M,
-    RE_S,
-    RE_MULTILINE,
-    RE_DOTALL,
+fn mpc_val_ref<O: 'static>(x: &mpc_val_t) -> &O {
+    if x.type_id != TypeId::of::<O>() {
+        panic!("Error: Parser produced a value of an unexpected type.");
+    }
+    unsafe { &*(x.ptr as *const O) }
 }
 
-fn mpc_re(re: &str) -> mpc_parser_t {
-    unimplemented!()
+fn mpc_val_clone<O: Clone + 'static>(i: &mpc_input_t, x: &mpc_val_t) -> mpc_val_t {
+    mpc_malloc(i, mpc_val_ref::<O>(x).clone())
 }
 
-fn mpc_re_mode(re: &str, mode: i32) -> mpc_parser_t {
-    unimplemented!()
+fn mpc_val_next<O: 'static>(xs: &mut impl Iterator<Item = mpc_val_t>) -> O {
+    match xs.next() {
+        Some(x) => mpc_val_take(x),
+        None => panic!("Error: Parser produced too few values."),
+    }
 }
 
-// AST
-struct mpc_ast_t {
-    tag: String,
-    contents: String,
-    state: mpc_state_t,
-    children_num: isize,
-    children: Vec<Option<Box<mpc_ast_t>>>,
+fn mpc_erase_ctor<O: 'static>(f: impl Fn() -> O + 'static) -> mpc_any_ctor_t {
+    Rc::new(move |i| mpc_malloc(i, f()))
 }
 
-fn mpc_ast_new(tag: &str, contents: &str) -> mpc_ast_t {
-    unimplemented!()
+fn mpc_erase_apply<I: 'static, O: 'static>(f: impl Fn(I) -> O + 'static) -> mpc_any_apply_t {
+    Rc::new(move |i, x| mpc_malloc(i, f(mpc_val_take(x))))
 }
 
-fn mpc_ast_build(n: i32, tag: &str, args: Arguments) -> mpc_ast_t {
-    unimplemented!()
+fn mpc_erase_check<O: 'static>(f: impl Fn(&O) -> bool + 'static) -> mpc_any_check_t {
+    Rc::new(move |x| f(mpc_val_ref(x)))
 }
 
-fn mpc_ast_add_root(a: &mpc_ast_t) -> mpc_ast_t {
-    unimplemented!()
+fn mpc_erase_fold<I: 'static, O: 'static>(f: impl Fn(Vec<I>) -> O + 'static) -> mpc_any_fold_t {
+    Rc::new(move |i, xs| mpc_malloc(i, f(xs.into_iter().map(mpc_val_take).collect())))
 }
 
-fn mpc_ast_add_child(r: &mpc_ast_t, a: &mpc_ast_t) -> mpc_ast_t {
-    unimplemented!()
+// Related Functions
+// Any error recovered from still fails the parse, the first one being
+// returned.
+pub fn mpc_parse_input<O: 'static>(i: &mut mpc_input_t, p: &Parser<O>) -> mpc_result_t<O> {
+    match mpc_parse_input_recover(i, p) {
+        (Some(x), es) if es.is_empty() => Ok(x),
+        (_, mut es) => Err(es.remove(0)),
+    }
 }
 
-fn mpc_ast_add_tag(a: &mpc_ast_t, t: &str) -> mpc_ast_t {
-    unimplemented!()
+// Produces whatever could be parsed along with every error recovered from,
+// followed by the error which stopped the parse if there was one.
+pub fn mpc_parse_input_recover<O: 'static>(
+    i: &mut mpc_input_t,
+    p: &Parser<O>,
+) -> (Option<O>, Vec<mpc_err_t>) {
+    let r = mpc_parse_run(i, p);
+    let mut es = std::mem::take(&mut i.errors);
+    if let Some(e) = i.io_error.take() {
+        return (None, vec![mpc_err_locate(i, e)]);
+    }
+    let x = match r {
+        Ok(x) => Some(mpc_export(x)),
+        Err(e) => {
+            es.push(e);
+            None
+        }
+    };
+    let es = es
+        .into_iter()
+        .map(|e| {
+            let e = mpc_err_suggest(i, e);
+            mpc_err_locate(i, e)
+        })
+        .collect();
+    (x, es)
+}
+
+fn mpc_parse_char(i: &mut mpc_input_t, x: u8, e: impl FnOnce() -> String) -> Result<(), mpc_err_t> {
+    if x == 1 {
+        Ok(())
+    } else {
+        Err(mpc_err_new(i, &e()))
+    }
 }
 
-fn mpc_ast_add_root_tag(a: &mpc_ast_t, t: &str) -> mpc_ast_t {
-    unimplemented!()
+fn mpc_parse_byte(i: &mut mpc_input_t, x: u8, e: impl FnOnce() -> String) -> mpc_result_t {
+    if x == 1 {
+        Ok(mpc_malloc(i, i.last as u8))
+    } else {
+        Err(mpc_err_new(i, &e()))
+    }
 }
 
-fn mpc_ast_tag(a: &mpc_ast_t, t: &str) -> mpc_ast_t {
-    unimplemented!()
+fn mpc_parse_child(i: &mut mpc_input_t, p: &mpc_parser_t) -> mpc_result_t {
+    match p.children.first() {
+        Some(a) => mpc_parse_run(i, a),
+        None => Err(mpc_err_fail(i, "Parser Undefined!")),
+    }
 }
 
-fn mpc_ast_state(a: &mpc_ast_t, s: mpc_state_t) -> mpc_ast_t {
-    unimplemented!()
+// Runs the child of `p` where its failure is not the end of things, so any
+// errors it recovered from on the way are dropped along with it.
+fn mpc_parse_try(i: &mut mpc_input_t, p: &mpc_parser_t) -> mpc_result_t {
+    let n = i.errors.len();
+    let r = mpc_parse_child(i, p);
+    if r.is_err() {
+        i.errors.truncate(n);
+    }
+    r
 }
 
-fn mpc_ast_delete(a: &mpc_ast_t) {}
-fn mpc_ast_print(a: &mpc_ast_t) {}
-fn mpc_ast_print_to(a: &mpc_ast_t, f: &mut File) {}
+// Runs the only child of `p` until it fails or `n` results are collected. An
+// unbounded repeat also stops once the child succeeds without consuming input.
+fn mpc_parse_repeat(
+    i: &mut mpc_input_t,
+    p: &mpc_parser_t,
+    n: Option<usize>,
+) -> (Vec<mpc_val_t>, mpc_err_t) {
+    let mut xs = vec![];
+
+    while n.is_none_or(|n| xs.len() < n) {
+        let pos = i.state.pos;
+        match mpc_parse_try(i, p) {
+            Ok(x) => xs.push(x),
+            Err(e) => return (xs, e),
+        }
+        if n.is_none() && i.state.pos == pos {
+            break;
+        }
+    }
 
-fn mpc_ast_get_index(ast: &mpc_ast_t, tag: &str) -> i32 {
-    unimplemented!()
-}
-fn mpc_ast_get_index_lb(ast: &mpc_ast_t, tag: &str, lb: i32) -> i32 {
-    unimplemented!()
+    let e = mpc_err_fail(i, "Unknown Error");
+    (xs, e)
 }
 
-fn mpc_ast_get_child(ast: &mpc_ast_t, tag: &str) -> mpc_ast_t {
-    unimplemented!()
-}
-fn mpc_ast_get_child_lb(ast: &mpc_ast_t, tag: &str, lb: i32) -> mpc_ast_t {
-    unimplemented!()
+// Matches `p` without building its result where that can be avoided.
+// Primitives write what they matched to `o` when given one.
+fn mpc_parse_match(
+    i: &mut mpc_input_t,
+    p: &mpc_parser_t,
+    o: Option<&mut String>,
+) -> Result<(), mpc_err_t> {
+    match &p.kind {
+        mpc_type_t::ANY => {
+            let x = mpc_input_any(i, o);
+            mpc_parse_char(i, x, || "any character".to_owned())
+        }
+        mpc_type_t::SINGLE(c) => {
+            let x = mpc_input_char(i, *c, o);
+            mpc_parse_char(i, x, || format!("'{c}'"))
+        }
+        mpc_type_t::RANGE(c, d) => {
+            let x = mpc_input_range(i, *c, *d, o);
+            mpc_parse_char(i, x, || format!("character between '{c}' and '{d}'"))
+        }
+        mpc_type_t::ONEOF(s) => {
+            let x = mpc_input_oneof(i, s, o);
+            mpc_parse_char(i, x, || format!("one of '{s}'"))
+        }
+        mpc_type_t::NONEOF(s) => {
+            let x = mpc_input_noneof(i, s, o);
+            mpc_parse_char(i, x, || format!("none of '{s}'"))
+        }
+        mpc_type_t::SATISFY(f) => {
+            let x = mpc_input_satisfy(i, *f, o);
+            mpc_parse_char(i, x, || "character satisfying function".to_owned())
+        }
+        mpc_type_t::STRING(s) => {
+            let x = mpc_input_string(i, s, o);
+            mpc_parse_char(i, x, || format!("\"{s}\""))
+        }
+        mpc_type_t::STRING_NOCASE(s) => {
+            let x = mpc_input_string_nocase(i, s, o);
+            mpc_parse_char(i, x, || format!("\"{s}\" in any case"))
+        }
+        mpc_type_t::STRING_ONEOF(ss) => {
+            let x = mpc_input_string_oneof(i, ss, o);
+            mpc_parse_char(i, x, || {
+                let ss: Vec<String> = ss.iter().map(|s| format!("\"{s}\"")).collect();
+                format!("one of {}", ss.join(", "))
+            })
+        }
+        _ => mpc_parse_run(i, p).map(drop),
+    }
 }
 
-enum mpc_ast_trav_order_t {
-    mpc_ast_trav_order_pre,
-    mpc_ast_trav_order_post,
-}
+// Left recursion is grown from a seed. Called back at the position it started
+// from, `p` fails at first. If that happened its definition `d` is parsed again
+// and again, the calls back now getting the previous parse, for as long as
+// the parse keeps getting further.
+fn mpc_parse_named(
+    i: &mut mpc_input_t,
+    p: &mpc_parser_t,
+    d: &mpc_parser_t,
+    clone: mpc_any_clone_t,
+) -> mpc_result_t {
+    let key = (p as *const mpc_parser_t, i.state.pos);
+    if let Some(lr) = i.lr.get_mut(&key) {
+        lr.used = true;
+        let seed = i.lr[&key]
+            .seed
+            .as_ref()
+            .map(|(s, c, x)| (*s, *c, clone(i, x)));
+        return match seed {
+            Some((s, c, x)) => {
+                mpc_input_jump(i, s, c);
+                Ok(x)
+            }
+            None => Err(mpc_err_recursion(i, p.name.as_deref().unwrap_or_default())),
+        };
+    }
 
-struct mpc_ast_trav_t {
-    curr_node: Option<Box<mpc_ast_t>>,
-    parent: Option<Box<mpc_ast_trav_t>>,
-    curr_child: i32,
-    order: mpc_ast_trav_order_t,
-}
+    let lr = mpc_lr_t {
+        used: false,
+        seed: None,
+    };
+    i.lr.insert(key, lr);
+    mpc_input_mark_always(i);
+
+    let n = i.errors.len();
+    let mut r = mpc_parse_run(i, d);
+    let mut errors = vec![];
+    if i.lr[&key].used {
+        r = loop {
+            let x = match r {
+                Ok(x) => x,
+                e => break e,
+            };
+            if i.lr[&key]
+                .seed
+                .as_ref()
+                .is_some_and(|(s, ..)| i.state.pos <= s.pos)
+            {
+                break Ok(x);
+            }
+            errors = i.errors.split_off(n);
+            i.lr.get_mut(&key).unwrap().seed = Some((i.state, i.last, x));
+            mpc_input_rewind_always(i);
+            mpc_input_mark_always(i);
+            r = mpc_parse_run(i, d);
+        };
+        i.errors.truncate(n);
+        i.errors.append(&mut errors);
+    }
 
-fn mpc_ast_traverse_start(ast: &mpc_ast_t, order: mpc_ast_trav_order_t) -> mpc_ast_trav_t {
-    unimplemented!()
+    let lr = i.lr.remove(&key).unwrap();
+    match (lr.seed, r) {
+        (Some((s, c, x)), _) => {
+            mpc_input_jump(i, s, c);
+            mpc_input_unmark_always(i);
+            Ok(x)
+        }
+        (None, Ok(x)) => {
+            mpc_input_unmark_always(i);
+            Ok(x)
+        }
+        (None, Err(e)) => {
+            mpc_input_rewind_always(i);
+            Err(e)
+        }
+    }
 }
 
-fn mpc_ast_traverse_next(trav: Vec<Option<Box<mpc_ast_trav_t>>>) -> mpc_ast_t {
-    unimplemented!()
-}
+fn mpc_parse_run(i: &mut mpc_input_t, p: &mpc_parser_t) -> mpc_result_t {
+    match &p.kind {
+        // Basic Parsers
+        mpc_type_t::ANY
+        | mpc_type_t::SINGLE(_)
+        | mpc_type_t::RANGE(..)
+        | mpc_type_t::ONEOF(_)
+        | mpc_type_t::NONEOF(_)
+        | mpc_type_t::SATISFY(_)
+        | mpc_type_t::STRING(_)
+        | mpc_type_t::STRING_NOCASE(_)
+        | mpc_type_t::STRING_ONEOF(_) => {
+            let mut o = String::new();
+            mpc_parse_match(i, p, Some(&mut o))?;
+            Ok(mpc_malloc(i, o))
+        }
+        mpc_type_t::ANCHOR(f) => {
+            if mpc_input_anchor(i, *f, None) == 1 {
+                Ok(mpc_malloc(i, ()))
+            } else {
+                Err(mpc_err_new(i, "anchor"))
+            }
+        }
 
-fn mpc_ast_traverse_free(trav: Vec<Option<Box<mpc_ast_trav_t>>>) {}
+        // Byte Parsers
+        mpc_type_t::ANY_BYTE => {
+            let x = mpc_input_any_byte(i);
+            mpc_parse_byte(i, x, || "any byte".to_owned())
+        }
+        mpc_type_t::BYTE_RANGE(c, d) if c == d => {
+            let x = mpc_input_byte_range(i, *c, *d);
+            mpc_parse_byte(i, x, || format!("byte 0x{c:02x}"))
+        }
+        mpc_type_t::BYTE_RANGE(c, d) => {
+            let x = mpc_input_byte_range(i, *c, *d);
+            mpc_parse_byte(i, x, || format!("byte between 0x{c:02x} and 0x{d:02x}"))
+        }
+        mpc_type_t::BLOCK => {
+            mpc_input_mark(i);
+            let n: usize = match mpc_parse_child(i, p) {
+                Ok(n) => mpc_val_take(n),
+                Err(e) => {
+                    mpc_input_rewind(i);
+                    return Err(e);
+                }
+            };
+
+            let mut xs = vec![];
+            while xs.len() < n && mpc_input_any_byte(i) == 1 {
+                xs.push(i.last as u8);
+            }
+
+            if xs.len() == n {
+                mpc_input_unmark(i);
+                Ok(mpc_malloc(i, xs))
+            } else {
+                let e = mpc_err_new(i, &format!("{} more bytes", n - xs.len()));
+                mpc_input_rewind(i);
+                Err(e)
+            }
+        }
+
+        // Other parsers
+        mpc_type_t::NAMED(d, clone) => {
+            let d = d.borrow().clone();
+            match d {
+                Some(d) => mpc_parse_named(i, p, &d, *clone),
+                None => {
+                    let name = p.name.as_deref().unwrap_or_default();
+                    Err(mpc_err_fail(i, &format!("Parser '{name}' Undefined!")))
+                }
+            }
+        }
+        mpc_type_t::PASS => Ok(mpc_malloc(i, ())),
+        mpc_type_t::FAIL(m, d) => Err(mpc_err_diag(mpc_err_fail(i, m), d)),
+        mpc_type_t::LIFT(f) => Ok(f(i)),
+        mpc_type_t::STATE => {
+            let s = mpc_input_state(i);
+            Ok(mpc_malloc(i, s))
+        }
+
+        // Application Parsers
+        mpc_type_t::APPLY(f) => mpc_parse_child(i, p).map(|x| f(i, x)),
+        mpc_type_t::CHECK(f, e, d) => {
+            let x = mpc_parse_child(i, p)?;
+            if f(&x) {
+                Ok(x)
+            } else {
+                Err(mpc_err_diag(mpc_err_fail(i, e), d))
+            }
+        }
+        mpc_type_t::SPAN => {
+            let start = mpc_input_state(i);
+            match p.children.first() {
+                Some(a) => mpc_parse_match(i, a, None)?,
+                None => return Err(mpc_err_fail(i, "Parser Undefined!")),
+            }
+            let end = mpc_input_state(i);
+            Ok(mpc_malloc(i, mpc_span_t { start, end }))
+        }
+        mpc_type_t::EXPECT(m, d) => {
+            let pos = i.state.pos;
+            match mpc_parse_child(i, p) {
+                Err(e) if e.state.pos <= pos => Err(mpc_err_diag(mpc_err_new(i, m), d)),
+                r => r,
+            }
+        }
+        mpc_type_t::RECOVER => {
+            let n = i.errors.len();
+            let e = match mpc_parse_try(i, p) {
+                Ok(x) => return Ok(x),
+                Err(e) => e,
+            };
+            match mpc_parse_run(i, &p.children[1]) {
+                Ok(x) => {
+                    i.errors.insert(n, e);
+                    Ok(x)
+                }
+                Err(_) => {
+                    i.errors.truncate(n);
+                    Err(e)
+                }
+            }
+        }
+        mpc_type_t::PREDICT => {
+            mpc_input_backtrack_disable(i);
+            let r = mpc_parse_child(i, p);
+            mpc_input_backtrack_enable(i);
+            r
+        }
+
+        // Optional Parsers
+        mpc_type_t::NOT(lf) => {
+            let n = i.errors.len();
+            mpc_input_mark(i);
+            mpc_input_suppress_enable(i);
+            match mpc_parse_try(i, p) {
+                Ok(_) => {
+                    i.errors.truncate(n);
+                    mpc_input_rewind(i);
+                    mpc_input_suppress_disable(i);
+                    Err(mpc_err_new(i, "opposite"))
+                }
+                Err(_) => {
+                    mpc_input_unmark(i);
+                    mpc_input_suppress_disable(i);
+                    Ok(lf(i))
+                }
+            }
+        }
+        mpc_type_t::MAYBE(lf) => mpc_parse_try(i, p).or_else(|_| Ok(lf(i))),
+
+        // Repeat Parsers
+        mpc_type_t::MANY(f) => {
+            let (xs, _) = mpc_parse_repeat(i, p, None);
+            Ok(f(i, xs))
+        }
+        mpc_type_t::MANY1(f) => {
+            let (xs, e) = mpc_parse_repeat(i, p, None);
+            if xs.is_empty() {
+                Err(mpc_err_many1(e))
+            } else {
+                Ok(f(i, xs))
+            }
+        }
+        mpc_type_t::COUNT(n, f) => {
+            mpc_input_mark(i);
+            let (xs, e) = mpc_parse_repeat(i, p, Some(*n));
+            if xs.len() == *n {
+                mpc_input_unmark(i);
+                Ok(f(i, xs))
+            } else {
+                mpc_input_rewind(i);
+                Err(mpc_err_count(e, *n))
+            }
+        }
+
+        // Combinatory Parsers
+        mpc_type_t::OR => {
+            let pos = i.state.pos;
+            let mut e = None;
+            for a in &p.children {
+                let n = i.errors.len();
+                match mpc_parse_run(i, a) {
+                    Ok(x) => return Ok(x),
+                    Err(x) => {
+                        i.errors.truncate(n);
+                        e = Some(match e {
+                            Some(e) => mpc_err_or(e, x),
+                            None => x,
+                        })
+                    }
+                }
+            }
+            match e {
+                Some(e) if e.state.pos == pos => Err(mpc_err_alternatives(p, e)),
+                Some(e) => Err(e),
+                None => Err(mpc_err_fail(i, "No Alternatives!")),
+            }
+        }
+        mpc_type_t::AND(f) => {
+            let mut xs = Vec::with_capacity(p.children.len());
+            mpc_input_mark(i);
+            for a in &p.children {
+                match mpc_parse_run(i, a) {
+                    Ok(x) => xs.push(x),
+                    Err(e) => {
+                        mpc_input_rewind(i);
+                        return Err(e);
+                    }
+                }
+            }
+            mpc_input_unmark(i);
+            Ok(f(i, xs))
+        }
+    }
+}
+
+pub fn mpc_parse<O: 'static>(filename: &str, string: &str, p: &Parser<O>) -> mpc_result_t<O> {
+    let mut i = mpc_input_new_string(filename, string);
+    mpc_parse_input(&mut i, p)
+}
+
+pub fn mpc_parse_recover<O: 'static>(
+    filename: &str,
+    string: &str,
+    p: &Parser<O>,
+) -> (Option<O>, Vec<mpc_err_t>) {
+    let mut i = mpc_input_new_string(filename, string);
+    mpc_parse_input_recover(&mut i, p)
+}
+
+pub fn mpc_nparse<O: 'static>(
+    filename: &str,
+    string: &str,
+    length: usize,
+    p: &Parser<O>,
+) -> mpc_result_t<O> {
+    let mut i = mpc_input_new_nstring(filename, string, length);
+    mpc_parse_input(&mut i, p)
+}
+
+pub fn mpc_parse_bytes<O: 'static>(filename: &str, bytes: &[u8], p: &Parser<O>) -> mpc_result_t<O> {
+    let mut i = mpc_input_new_bytes(filename, bytes);
+    mpc_parse_input(&mut i, p)
+}
+
+pub fn mpc_parse_file<O: 'static, R: Read + Seek>(
+    filename: &str,
+    file: R,
+    p: &Parser<O>,
+) -> mpc_result_t<O> {
+    let mut i = mpc_input_new_file(filename, file);
+    mpc_parse_input(&mut i, p)
+}
+
+pub fn mpc_parse_pipe<O: 'static, R: Read>(
+    filename: &str,
+    pipe: R,
+    p: &Parser<O>,
+) -> mpc_result_t<O> {
+    let mut i = mpc_input_new_pipe(filename, pipe);
+    mpc_parse_input(&mut i, p)
+}
+
+// Offsets are `i32`, so files of 2 GiB or more are refused.
+pub fn mpc_parse_contents<O: 'static>(filename: &str, p: &Parser<O>) -> mpc_result_t<O> {
+    let f = match File::open(filename) {
+        Ok(f) => f,
+        Err(_) => return Err(mpc_err_file(filename, "Unable to open file!")),
+    };
+
+    if f.metadata().is_ok_and(|m| m.len() > i32::MAX as u64) {
+        return Err(mpc_err_file(filename, "File too large!"));
+    }
+
+    let file = mpc_file_buffered_t {
+        file: BufReader::new(f),
+        pos: 0,
+    };
+    mpc_parse_file(filename, file, p)
+}
+
+/// Like `mpc_parse_contents`, but maps a regular file into memory rather than
+/// reading it. Anything else, or a file which can't be mapped, is read.
+///
+/// # Safety
+///
+/// The file must not be modified, by this process or any other, while it is
+/// parsed. A file changing under the parse is undefined behaviour, not just a
+/// wrong result.
+#[cfg(feature = "mmap")]
+pub unsafe fn mpc_parse_contents_mmap<O: 'static>(
+    filename: &str,
+    p: &Parser<O>,
+) -> mpc_result_t<O> {
+    if let Ok(f) = File::open(filename) {
+        if f.metadata().is_ok_and(|m| m.is_file()) {
+            if let Ok(mut i) = unsafe { mpc_input_new_mmap(filename, &f) } {
+                return mpc_parse_input(&mut i, p);
+            }
+        }
+    }
+
+    mpc_parse_contents(filename, p)
+}
+
+// Function Types
+
+pub type mpc_ctor_t<O> = fn() -> O;
+
+pub type mpc_apply_t<I, O> = fn(I) -> O;
+pub type mpc_apply_to_t<I, X, O> = fn(I, &X) -> O;
+pub type mpc_fold_t<I, O> = fn(Vec<I>) -> O;
+
+pub type mpc_check_t<O> = fn(&O) -> bool;
+pub type mpc_check_with_t<O, X> = fn(&O, &X) -> bool;
+
+// Building a Parser
+// A named parser can be used before it is defined, and so refer to itself or
+// others which refer back to it. Parsing with it while undefined fails. Its
+// output is cloned, as each call back into a left recursion gets its own copy
+// of the parse so far. Outputs which can't be cloned can be put in an `Rc`.
+pub fn mpc_new<O: Clone + 'static>(name: &str) -> Parser<O> {
+    let p = mpc_parser_t {
+        name: Some(name.to_owned()),
+        kind: mpc_type_t::NAMED(RefCell::new(None), mpc_val_clone::<O>),
+        children: vec![],
+        retained: true,
+        reaches_named: true,
+        handles: Cell::new(0),
+    };
+    mpc_handle(Rc::new(p))
+}
+
+// Named parsers are copied with their current definition, which the copy can
+// then be given anew without affecting the original.
+pub fn mpc_copy<O>(a: &Parser<O>) -> Parser<O> {
+    let p = mpc_parser_t {
+        handles: Cell::new(0),
+        ..mpc_parser_t::clone(&a.p)
+    };
+    mpc_handle(Rc::new(p))
+}
+
+pub fn mpc_define<O>(p: &Parser<O>, a: &Parser<O>) -> Parser<O> {
+    match &p.kind {
+        mpc_type_t::NAMED(d, _) => {
+            let old = d.replace(Some(a.p.clone()));
+            if let Some(old) = old {
+                mpc_collect(&old);
+            }
+        }
+        _ => panic!("Error: Only parsers from mpc_new can be defined."),
+    }
+    p.clone()
+}
+
+pub fn mpc_undefine<O>(p: &Parser<O>) -> Parser<O> {
+    mpc_undefine_unretained(&p.p);
+    p.clone()
+}
+
+fn mpc_undefine_unretained(p: &mpc_parser_t) {
+    if let mpc_type_t::NAMED(d, _) = &p.kind {
+        let old = d.take();
+        if let Some(old) = old {
+            mpc_collect(&old);
+        }
+    }
+}
+
+// Parsers are freed once nothing refers to them, cycles included. Deleting
+// one only undefines it, so whatever still refers to it fails cleanly.
+pub fn mpc_delete<O>(p: &Parser<O>) {
+    mpc_undefine_unretained(&p.p);
+}
+pub fn mpc_cleanup(ps: &[&mpc_parser_t]) {
+    for p in ps {
+        mpc_undefine_unretained(p);
+    }
+}
+
+// Basic Parsers
+pub fn mpc_any() -> Parser<String> {
+    mpc_node(mpc_type_t::ANY, vec![])
+}
+
+pub fn mpc_char(c: char) -> Parser<String> {
+    mpc_node(mpc_type_t::SINGLE(c), vec![])
+}
+
+pub fn mpc_range(s: char, e: char) -> Parser<String> {
+    mpc_node(mpc_type_t::RANGE(s, e), vec![])
+}
+
+pub fn mpc_oneof(s: &str) -> Parser<String> {
+    mpc_node(mpc_type_t::ONEOF(s.to_owned()), vec![])
+}
+
+pub fn mpc_noneof(s: &str) -> Parser<String> {
+    mpc_node(mpc_type_t::NONEOF(s.to_owned()), vec![])
+}
+
+pub fn mpc_satisfy(f: fn(char) -> bool) -> Parser<String> {
+    mpc_node(mpc_type_t::SATISFY(f), vec![])
+}
+
+pub fn mpc_string(s: &str) -> Parser<String> {
+    mpc_node(mpc_type_t::STRING(s.to_owned()), vec![])
+}
+
+pub fn mpc_string_nocase(s: &str) -> Parser<String> {
+    mpc_node(mpc_type_t::STRING_NOCASE(s.to_owned()), vec![])
+}
+
+// Matches the longest of the strings, e.g. "<=" before "<".
+pub fn mpc_string_oneof(ss: &[&str]) -> Parser<String> {
+    let mut ss: Vec<String> = ss.iter().map(|s| s.to_string()).collect();
+    ss.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    ss.dedup();
+    mpc_node(mpc_type_t::STRING_ONEOF(ss), vec![])
+}
+
+// Byte Parsers
+
+pub fn mpc_any_byte() -> Parser<u8> {
+    mpc_node(mpc_type_t::ANY_BYTE, vec![])
+}
+
+pub fn mpc_byte(b: u8) -> Parser<u8> {
+    mpc_node(mpc_type_t::BYTE_RANGE(b, b), vec![])
+}
+
+pub fn mpc_byte_range(s: u8, e: u8) -> Parser<u8> {
+    mpc_node(mpc_type_t::BYTE_RANGE(s, e), vec![])
+}
+
+pub fn mpc_bytes(n: usize) -> Parser<Vec<u8>> {
+    mpc_count(n, |xs| xs, &mpc_any_byte())
+}
+
+// Reads as many bytes as the value produced by `n`.
+pub fn mpc_block(n: &Parser<usize>) -> Parser<Vec<u8>> {
+    mpc_node(mpc_type_t::BLOCK, vec![n.p.clone()])
+}
+
+pub fn mpc_be_u16() -> Parser<u16> {
+    mpc_apply(&mpc_bytes(2), |b| u16::from_be_bytes([b[0], b[1]]))
+}
+pub fn mpc_le_u16() -> Parser<u16> {
+    mpc_apply(&mpc_bytes(2), |b| u16::from_le_bytes([b[0], b[1]]))
+}
+pub fn mpc_be_u32() -> Parser<u32> {
+    mpc_apply(&mpc_bytes(4), |b| {
+        u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+    })
+}
+pub fn mpc_le_u32() -> Parser<u32> {
+    mpc_apply(&mpc_bytes(4), |b| {
+        u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+    })
+}
+pub fn mpc_be_u64() -> Parser<u64> {
+    mpc_apply(&mpc_bytes(8), |b| u64::from_be_bytes(b.try_into().unwrap()))
+}
+pub fn mpc_le_u64() -> Parser<u64> {
+    mpc_apply(&mpc_bytes(8), |b| u64::from_le_bytes(b.try_into().unwrap()))
+}
+
+pub fn mpc_u8_block() -> Parser<Vec<u8>> {
+    mpc_block(&mpc_apply(&mpc_any_byte(), |n| n as usize))
+}
+pub fn mpc_be_u16_block() -> Parser<Vec<u8>> {
+    mpc_block(&mpc_apply(&mpc_be_u16(), |n| n as usize))
+}
+pub fn mpc_le_u16_block() -> Parser<Vec<u8>> {
+    mpc_block(&mpc_apply(&mpc_le_u16(), |n| n as usize))
+}
+pub fn mpc_be_u32_block() -> Parser<Vec<u8>> {
+    mpc_block(&mpc_apply(&mpc_be_u32(), |n| n as usize))
+}
+pub fn mpc_le_u32_block() -> Parser<Vec<u8>> {
+    mpc_block(&mpc_apply(&mpc_le_u32(), |n| n as usize))
+}
+
+// Other Parsers
+
+pub fn mpc_pass() -> Parser<()> {
+    mpc_node(mpc_type_t::PASS, vec![])
+}
+
+pub fn mpc_fail<O>(m: &str) -> Parser<O> {
+    mpc_node(mpc_type_t::FAIL(m.to_owned(), None), vec![])
+}
+
+pub fn mpc_failf<O>(args: Arguments) -> Parser<O> {
+    mpc_fail(&std::fmt::format(args))
+}
+
+pub fn mpc_fail_diag<O>(m: &str, d: mpc_diag_t) -> Parser<O> {
+    mpc_node(mpc_type_t::FAIL(m.to_owned(), Some(Rc::new(d))), vec![])
+}
+
+pub fn mpc_failf_diag<O>(d: mpc_diag_t, args: Arguments) -> Parser<O> {
+    mpc_fail_diag(&std::fmt::format(args), d)
+}
+
+pub fn mpc_lift<O: 'static>(f: mpc_ctor_t<O>) -> Parser<O> {
+    mpc_node(mpc_type_t::LIFT(mpc_erase_ctor(f)), vec![])
+}
+
+pub fn mpc_lift_val<O: Clone + 'static>(x: O) -> Parser<O> {
+    mpc_node(mpc_type_t::LIFT(mpc_erase_ctor(move || x.clone())), vec![])
+}
+
+// `f` is given the characters before and after the position, `None` standing
+// for the start and end of the input.
+pub fn mpc_anchor(f: fn(Option<char>, Option<char>) -> bool) -> Parser<()> {
+    mpc_node(mpc_type_t::ANCHOR(f), vec![])
+}
+
+pub fn mpc_state() -> Parser<mpc_state_t> {
+    mpc_node(mpc_type_t::STATE, vec![])
+}
+
+// Combinator Parsers
+// Names what `a` expects, unless it failed only after consuming some input
+// in which case its own error is more helpful.
+pub fn mpc_expect<O>(a: &Parser<O>, e: &str) -> Parser<O> {
+    mpc_node(mpc_type_t::EXPECT(e.to_owned(), None), vec![a.p.clone()])
+}
+
+pub fn mpc_expectf<O>(a: &Parser<O>, args: Arguments) -> Parser<O> {
+    mpc_expect(a, &std::fmt::format(args))
+}
+
+// `d` only goes on the error naming `e`, not those `a` fails with itself.
+pub fn mpc_expect_diag<O>(a: &Parser<O>, e: &str, d: mpc_diag_t) -> Parser<O> {
+    let kind = mpc_type_t::EXPECT(e.to_owned(), Some(Rc::new(d)));
+    mpc_node(kind, vec![a.p.clone()])
+}
+
+pub fn mpc_apply<I: 'static, O: 'static>(a: &Parser<I>, f: mpc_apply_t<I, O>) -> Parser<O> {
+    mpc_node(mpc_type_t::APPLY(mpc_erase_apply(f)), vec![a.p.clone()])
+}
+
+pub fn mpc_apply_to<I: 'static, X: 'static, O: 'static>(
+    a: &Parser<I>,
+    f: mpc_apply_to_t<I, X, O>,
+    x: X,
+) -> Parser<O> {
+    let f = mpc_erase_apply(move |y| f(y, &x));
+    mpc_node(mpc_type_t::APPLY(f), vec![a.p.clone()])
+}
+
+// Matches `a` but produces the span of input it consumed rather than its value.
+// Primitives don't even copy out what they matched.
+pub fn mpc_span<O>(a: &Parser<O>) -> Parser<mpc_span_t> {
+    mpc_node(mpc_type_t::SPAN, vec![a.p.clone()])
+}
+
+pub fn mpc_check<O: 'static>(a: &Parser<O>, f: mpc_check_t<O>, e: &str) -> Parser<O> {
+    let f = mpc_erase_check(f);
+    mpc_node(mpc_type_t::CHECK(f, e.to_owned(), None), vec![a.p.clone()])
+}
+
+pub fn mpc_check_diag<O: 'static>(
+    a: &Parser<O>,
+    f: mpc_check_t<O>,
+    e: &str,
+    d: mpc_diag_t,
+) -> Parser<O> {
+    let f = mpc_erase_check(f);
+    let kind = mpc_type_t::CHECK(f, e.to_owned(), Some(Rc::new(d)));
+    mpc_node(kind, vec![a.p.clone()])
+}
+
+pub fn mpc_check_with<O: 'static, X: 'static>(
+    a: &Parser<O>,
+    f: mpc_check_with_t<O, X>,
+    x: X,
+    e: &str,
+) -> Parser<O> {
+    let f = mpc_erase_check(move |y| f(y, &x));
+    mpc_node(mpc_type_t::CHECK(f, e.to_owned(), None), vec![a.p.clone()])
+}
+
+pub fn mpc_checkf<O: 'static>(a: &Parser<O>, f: mpc_check_t<O>, args: Arguments) -> Parser<O> {
+    mpc_check(a, f, &std::fmt::format(args))
+}
+
+pub fn mpc_check_withf<O: 'static, X: 'static>(
+    a: &Parser<O>,
+    f: mpc_check_with_t<O, X>,
+    x: X,
+    args: Arguments,
+) -> Parser<O> {
+    mpc_check_with(a, f, x, &std::fmt::format(args))
+}
+
+pub fn mpc_not<O>(a: &Parser<O>) -> Parser<()> {
+    mpc_not_lift(a, mpcf_ctor_null)
+}
+
+pub fn mpc_not_lift<O, L: 'static>(a: &Parser<O>, lf: mpc_ctor_t<L>) -> Parser<L> {
+    mpc_node(mpc_type_t::NOT(mpc_erase_ctor(lf)), vec![a.p.clone()])
+}
+
+pub fn mpc_maybe<O: 'static>(a: &Parser<O>) -> Parser<Option<O>> {
+    mpc_maybe_lift(&mpc_apply(a, Some), || None)
+}
+
+pub fn mpc_maybe_lift<O: 'static>(a: &Parser<O>, lf: mpc_ctor_t<O>) -> Parser<O> {
+    mpc_node(mpc_type_t::MAYBE(mpc_erase_ctor(lf)), vec![a.p.clone()])
+}
+
+pub fn mpc_many<I: 'static, O: 'static>(f: mpc_fold_t<I, O>, a: &Parser<I>) -> Parser<O> {
+    mpc_node(mpc_type_t::MANY(mpc_erase_fold(f)), vec![a.p.clone()])
+}
+
+pub fn mpc_many1<I: 'static, O: 'static>(f: mpc_fold_t<I, O>, a: &Parser<I>) -> Parser<O> {
+    mpc_node(mpc_type_t::MANY1(mpc_erase_fold(f)), vec![a.p.clone()])
+}
+
+pub fn mpc_count<I: 'static, O: 'static>(
+    n: usize,
+    f: mpc_fold_t<I, O>,
+    a: &Parser<I>,
+) -> Parser<O> {
+    mpc_node(mpc_type_t::COUNT(n, mpc_erase_fold(f)), vec![a.p.clone()])
+}
+
+pub fn mpc_or<O>(ps: &[&Parser<O>]) -> Parser<O> {
+    let children = ps.iter().map(|a| a.p.clone()).collect();
+    mpc_node(mpc_type_t::OR, children)
+}
+
+pub fn mpc_and<I: 'static, O: 'static>(f: mpc_fold_t<I, O>, ps: &[&Parser<I>]) -> Parser<O> {
+    let children = ps.iter().map(|a| a.p.clone()).collect();
+    mpc_node(mpc_type_t::AND(mpc_erase_fold(f)), children)
+}
+
+// Sequences of parsers with differing output types.
+pub fn mpc_and2<A: 'static, B: 'static, O: 'static>(
+    f: fn(A, B) -> O,
+    a: &Parser<A>,
+    b: &Parser<B>,
+) -> Parser<O> {
+    let fold: mpc_any_fold_t = Rc::new(move |i, xs| {
+        let mut xs = xs.into_iter();
+        let a = mpc_val_next(&mut xs);
+        mpc_malloc(i, f(a, mpc_val_next(&mut xs)))
+    });
+    mpc_node(mpc_type_t::AND(fold), vec![a.p.clone(), b.p.clone()])
+}
+
+pub fn mpc_and3<A: 'static, B: 'static, C: 'static, O: 'static>(
+    f: fn(A, B, C) -> O,
+    a: &Parser<A>,
+    b: &Parser<B>,
+    c: &Parser<C>,
+) -> Parser<O> {
+    let fold: mpc_any_fold_t = Rc::new(move |i, xs| {
+        let mut xs = xs.into_iter();
+        let a = mpc_val_next(&mut xs);
+        let b = mpc_val_next(&mut xs);
+        mpc_malloc(i, f(a, b, mpc_val_next(&mut xs)))
+    });
+    let children = vec![a.p.clone(), b.p.clone(), c.p.clone()];
+    mpc_node(mpc_type_t::AND(fold), children)
+}
+
+pub fn mpc_predictive<O>(a: &Parser<O>) -> Parser<O> {
+    mpc_node(mpc_type_t::PREDICT, vec![a.p.clone()])
+}
+
+// Recovery Parsers
+
+// If `a` fails its error is logged and `f` tried in its place, the parse only
+// failing if `f` does too.
+pub fn mpc_recover<O>(a: &Parser<O>, f: &Parser<O>) -> Parser<O> {
+    mpc_node(mpc_type_t::RECOVER, vec![a.p.clone(), f.p.clone()])
+}
+
+// Skips up to but not including `sync`, handing `f` what was skipped. Having
+// nothing to skip counts as failing to recover.
+pub fn mpc_recover_until<O: 'static, S>(
+    a: &Parser<O>,
+    sync: &Parser<S>,
+    f: mpc_apply_t<String, O>,
+) -> Parser<O> {
+    mpc_recover(a, &mpc_apply(&mpc_skip_until(sync), f))
+}
+
+// Carries on as if what `a` expected had been there.
+pub fn mpc_recover_insert<O: 'static>(a: &Parser<O>, lf: mpc_ctor_t<O>) -> Parser<O> {
+    mpc_recover(a, &mpc_lift(lf))
+}
+
+fn mpc_skip_until<S>(sync: &Parser<S>) -> Parser<String> {
+    let c = mpc_and2(|_, c| c, &mpc_not(sync), &mpc_any());
+    mpc_many1(mpcf_strfold, &c)
+}
+
+// Common Parsers
+
+fn mpc_is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn mpc_soi_anchor(prev: Option<char>, _next: Option<char>) -> bool {
+    prev.is_none()
+}
+
+fn mpc_eoi_anchor(_prev: Option<char>, next: Option<char>) -> bool {
+    next.is_none()
+}
+
+fn mpc_boundary_anchor(prev: Option<char>, next: Option<char>) -> bool {
+    prev.is_some_and(mpc_is_word) != next.is_some_and(mpc_is_word)
+}
+
+fn mpc_boundary_newline_anchor(prev: Option<char>, _next: Option<char>) -> bool {
+    prev == Some('\n')
+}
+
+pub fn mpc_eoi() -> Parser<()> {
+    mpc_expect(&mpc_anchor(mpc_eoi_anchor), "end of input")
+}
+
+pub fn mpc_soi() -> Parser<()> {
+    mpc_expect(&mpc_anchor(mpc_soi_anchor), "start of input")
+}
+
+pub fn mpc_boundary() -> Parser<()> {
+    mpc_expect(&mpc_anchor(mpc_boundary_anchor), "boundary")
+}
+
+pub fn mpc_boundary_newline() -> Parser<()> {
+    mpc_expect(&mpc_anchor(mpc_boundary_newline_anchor), "start of newline")
+}
+
+pub fn mpc_whitespace() -> Parser<String> {
+    mpc_expect(&mpc_oneof(" \x0c\n\r\t\x0b"), "whitespace")
+}
+
+pub fn mpc_whitespaces() -> Parser<String> {
+    mpc_expect(&mpc_many(mpcf_strfold, &mpc_whitespace()), "spaces")
+}
+
+pub fn mpc_blank() -> Parser<()> {
+    mpc_expect(&mpc_apply(&mpc_whitespaces(), mpcf_free), "whitespace")
+}
+
+pub fn mpc_digit() -> Parser<String> {
+    mpc_expect(&mpc_oneof("0123456789"), "digit")
+}
+
+pub fn mpc_hexdigit() -> Parser<String> {
+    mpc_expect(&mpc_oneof("0123456789ABCDEFabcdef"), "hex digit")
+}
+
+pub fn mpc_octdigit() -> Parser<String> {
+    mpc_expect(&mpc_oneof("01234567"), "oct digit")
+}
+
+pub fn mpc_digits() -> Parser<String> {
+    mpc_expect(&mpc_many1(mpcf_strfold, &mpc_digit()), "digits")
+}
+
+pub fn mpc_hexdigits() -> Parser<String> {
+    mpc_expect(&mpc_many1(mpcf_strfold, &mpc_hexdigit()), "hex digits")
+}
+
+pub fn mpc_octdigits() -> Parser<String> {
+    mpc_expect(&mpc_many1(mpcf_strfold, &mpc_octdigit()), "oct digits")
+}
+
+pub fn mpc_lower() -> Parser<String> {
+    mpc_expect(&mpc_oneof("abcdefghijklmnopqrstuvwxyz"), "lowercase letter")
+}
+pub fn mpc_upper() -> Parser<String> {
+    mpc_expect(&mpc_oneof("ABCDEFGHIJKLMNOPQRSTUVWXYZ"), "uppercase letter")
+}
+pub fn mpc_alpha() -> Parser<String> {
+    mpc_expect(
+        &mpc_oneof("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ"),
+        "letter",
+    )
+}
+pub fn mpc_underscore() -> Parser<String> {
+    mpc_expect(&mpc_char('_'), "underscore")
+}
+pub fn mpc_alphanum() -> Parser<String> {
+    mpc_expect(
+        &mpc_or(&[&mpc_alpha(), &mpc_digit(), &mpc_underscore()]),
+        "alphanumeric",
+    )
+}
+
+pub fn mpc_int() -> Parser<i32> {
+    mpc_expect(&mpc_apply(&mpc_digits(), mpcf_int), "integer")
+}
+pub fn mpc_hex() -> Parser<i32> {
+    mpc_expect(&mpc_apply(&mpc_hexdigits(), mpcf_hex), "hexadecimal")
+}
+pub fn mpc_oct() -> Parser<i32> {
+    mpc_expect(&mpc_apply(&mpc_octdigits(), mpcf_oct), "octadecimal")
+}
+pub fn mpc_number() -> Parser<i32> {
+    mpc_expect(&mpc_or(&[&mpc_int(), &mpc_hex(), &mpc_oct()]), "number")
+}
+
+pub fn mpc_real() -> Parser<String> {
+    let p0 = mpc_maybe_lift(&mpc_oneof("+-"), mpcf_ctor_str);
+    let p1 = mpc_digits();
+    let p2 = mpc_maybe_lift(
+        &mpc_and(mpcf_strfold, &[&mpc_char('.'), &mpc_digits()]),
+        mpcf_ctor_str,
+    );
+    let p30 = mpc_oneof("eE");
+    let p31 = mpc_maybe_lift(&mpc_oneof("+-"), mpcf_ctor_str);
+    let p32 = mpc_digits();
+    let p3 = mpc_maybe_lift(&mpc_and(mpcf_strfold, &[&p30, &p31, &p32]), mpcf_ctor_str);
+
+    mpc_expect(&mpc_and(mpcf_strfold, &[&p0, &p1, &p2, &p3]), "real")
+}
+pub fn mpc_float() -> Parser<f32> {
+    mpc_expect(&mpc_apply(&mpc_real(), mpcf_float), "float")
+}
+
+pub fn mpc_char_lit() -> Parser<String> {
+    let escaped = mpc_and(mpcf_strfold, &[&mpc_char('\\'), &mpc_any()]);
+    mpc_expect(
+        &mpc_between(&mpc_or(&[&escaped, &mpc_any()]), "'", "'"),
+        "char",
+    )
+}
+
+pub fn mpc_string_lit() -> Parser<String> {
+    let escaped = mpc_and(mpcf_strfold, &[&mpc_char('\\'), &mpc_any()]);
+    let strchar = mpc_or(&[&escaped, &mpc_noneof("\"")]);
+    mpc_expect(
+        &mpc_between(&mpc_many(mpcf_strfold, &strchar), "\"", "\""),
+        "string",
+    )
+}
+
+pub fn mpc_regex_lit() -> Parser<String> {
+    let escaped = mpc_and(mpcf_strfold, &[&mpc_char('\\'), &mpc_any()]);
+    let regexchar = mpc_or(&[&escaped, &mpc_noneof("/")]);
+    mpc_expect(
+        &mpc_between(&mpc_many(mpcf_strfold, &regexchar), "/", "/"),
+        "regex",
+    )
+}
+
+// Useful Parsers
+
+pub fn mpc_startwith<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_and2(|_, x| x, &mpc_soi(), a)
+}
+
+pub fn mpc_endwith<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_and2(|x, _| x, a, &mpc_eoi())
+}
+
+pub fn mpc_whole<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_and3(|_, x, _| x, &mpc_soi(), a, &mpc_eoi())
+}
+
+pub fn mpc_stripl<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_and2(|_, x| x, &mpc_blank(), a)
+}
+pub fn mpc_stripr<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_and2(|x, _| x, a, &mpc_blank())
+}
+pub fn mpc_strip<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_and3(|_, x, _| x, &mpc_blank(), a, &mpc_blank())
+}
+pub fn mpc_tok<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_and2(|x, _| x, a, &mpc_blank())
+}
+pub fn mpc_sym(s: &str) -> Parser<String> {
+    mpc_tok(&mpc_string(s))
+}
+pub fn mpc_total<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_whole(&mpc_strip(a))
+}
+
+pub fn mpc_between<O: 'static>(a: &Parser<O>, o: &str, c: &str) -> Parser<O> {
+    mpc_and3(|_, x, _| x, &mpc_string(o), a, &mpc_string(c))
+}
+pub fn mpc_parens<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_between(a, "(", ")")
+}
+pub fn mpc_braces<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_between(a, "<", ">")
+}
+pub fn mpc_brackets<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_between(a, "{", "}")
+}
+pub fn mpc_squares<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_between(a, "[", "]")
+}
+
+pub fn mpc_tok_between<O: 'static>(a: &Parser<O>, o: &str, c: &str) -> Parser<O> {
+    mpc_and3(|_, x, _| x, &mpc_sym(o), a, &mpc_sym(c))
+}
+pub fn mpc_tok_parens<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_tok_between(a, "(", ")")
+}
+pub fn mpc_tok_braces<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_tok_between(a, "<", ">")
+}
+pub fn mpc_tok_brackets<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_tok_between(a, "{", "}")
+}
+pub fn mpc_tok_squares<O: 'static>(a: &Parser<O>) -> Parser<O> {
+    mpc_tok_between(a, "[", "]")
+}
+
+// Common Function Parameters
+
+pub fn mpcf_ctor_null() {}
+pub fn mpcf_ctor_str() -> String {
+    String::new()
+}
+
+fn mpc_isspace(c: char) -> bool {
+    " \x0c\n\r\t\x0b".contains(c)
+}
+
+// Mirrors strtol: optional sign, as many digits as match, saturating on overflow.
+fn mpcf_strtol(s: &str, radix: u32) -> i32 {
+    let s = s.trim_start_matches(mpc_isspace);
+    let (neg, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+
+    let mut x: i64 = 0;
+    for d in s.chars().map_while(|c| c.to_digit(radix)) {
+        x = (x * radix as i64 + d as i64).min(i32::MAX as i64 + 1);
+    }
+
+    if neg {
+        (-x).max(i32::MIN as i64) as i32
+    } else {
+        x.min(i32::MAX as i64) as i32
+    }
+}
+
+pub fn mpcf_free<I>(_x: I) {}
+pub fn mpcf_int(x: String) -> i32 {
+    mpcf_strtol(&x, 10)
+}
+pub fn mpcf_hex(x: String) -> i32 {
+    mpcf_strtol(&x, 16)
+}
+pub fn mpcf_oct(x: String) -> i32 {
+    mpcf_strtol(&x, 8)
+}
+pub fn mpcf_float(x: String) -> f32 {
+    x.parse::<f32>().unwrap_or(0.0)
+}
+pub fn mpcf_strtriml(x: String) -> String {
+    x.trim_start_matches(mpc_isspace).to_owned()
+}
+pub fn mpcf_strtrimr(x: String) -> String {
+    x.trim_end_matches(mpc_isspace).to_owned()
+}
+pub fn mpcf_strtrim(x: String) -> String {
+    x.trim_matches(mpc_isspace).to_owned()
+}
+
+const MPC_ESCAPE_INPUT_C: [char; 11] = [
+    '\x07', '\x08', '\x0c', '\n', '\r', '\t', '\x0b', '\\', '\'', '"', '\0',
+];
+const MPC_ESCAPE_OUTPUT_C: [&str; 11] = [
+    "\\a", "\\b", "\\f", "\\n", "\\r", "\\t", "\\v", "\\\\", "\\'", "\\\"", "\\0",
+];
+
+const MPC_ESCAPE_INPUT_RAW_RE: [char; 1] = ['/'];
+const MPC_ESCAPE_OUTPUT_RAW_RE: [&str; 1] = ["\\/"];
+
+const MPC_ESCAPE_INPUT_RAW_CSTR: [char; 1] = ['"'];
+const MPC_ESCAPE_OUTPUT_RAW_CSTR: [&str; 1] = ["\\\""];
+
+const MPC_ESCAPE_INPUT_RAW_CCHAR: [char; 1] = ['\''];
+const MPC_ESCAPE_OUTPUT_RAW_CCHAR: [&str; 1] = ["\\'"];
+
+fn mpcf_escape_new(x: &str, input: &[char], output: &[&str]) -> String {
+    let mut y = String::with_capacity(x.len());
+    for c in x.chars() {
+        match input.iter().position(|&e| e == c) {
+            Some(k) => y.push_str(output[k]),
+            None => y.push(c),
+        }
+    }
+    y
+}
+
+fn mpcf_unescape_new(x: &str, input: &[char], output: &[&str]) -> String {
+    let mut y = String::with_capacity(x.len());
+    let mut s = x;
+    while let Some(c) = s.chars().next() {
+        match output.iter().position(|e| s.starts_with(e)) {
+            Some(k) => {
+                y.push(input[k]);
+                s = &s[output[k].len()..];
+            }
+            None => {
+                y.push(c);
+                s = &s[c.len_utf8()..];
+            }
+        }
+    }
+    y
+}
+
+pub fn mpcf_escape(x: String) -> String {
+    mpcf_escape_new(&x, &MPC_ESCAPE_INPUT_C, &MPC_ESCAPE_OUTPUT_C)
+}
+pub fn mpcf_escape_regex(x: String) -> String {
+    mpcf_escape_new(&x, &MPC_ESCAPE_INPUT_RAW_RE, &MPC_ESCAPE_OUTPUT_RAW_RE)
+}
+pub fn mpcf_escape_string_raw(x: String) -> String {
+    mpcf_escape_new(&x, &MPC_ESCAPE_INPUT_RAW_CSTR, &MPC_ESCAPE_OUTPUT_RAW_CSTR)
+}
+pub fn mpcf_escape_char_raw(x: String) -> String {
+    mpcf_escape_new(
+        &x,
+        &MPC_ESCAPE_INPUT_RAW_CCHAR,
+        &MPC_ESCAPE_OUTPUT_RAW_CCHAR,
+    )
+}
+
+pub fn mpcf_unescape(x: String) -> String {
+    mpcf_unescape_new(&x, &MPC_ESCAPE_INPUT_C, &MPC_ESCAPE_OUTPUT_C)
+}
+pub fn mpcf_unescape_regex(x: String) -> String {
+    mpcf_unescape_new(&x, &MPC_ESCAPE_INPUT_RAW_RE, &MPC_ESCAPE_OUTPUT_RAW_RE)
+}
+pub fn mpcf_unescape_string_raw(x: String) -> String {
+    mpcf_unescape_new(&x, &MPC_ESCAPE_INPUT_RAW_CSTR, &MPC_ESCAPE_OUTPUT_RAW_CSTR)
+}
+pub fn mpcf_unescape_char_raw(x: String) -> String {
+    mpcf_unescape_new(
+        &x,
+        &MPC_ESCAPE_INPUT_RAW_CCHAR,
+        &MPC_ESCAPE_OUTPUT_RAW_CCHAR,
+    )
+}
+
+fn mpcf_nth<I>(xs: Vec<I>, n: usize) -> I {
+    match xs.into_iter().nth(n) {
+        Some(x) => x,
+        None => panic!("Error: Fold expected at least {} values.", n + 1),
+    }
+}
+
+pub fn mpcf_null<I>(_xs: Vec<I>) {}
+pub fn mpcf_fst<I>(xs: Vec<I>) -> I {
+    mpcf_nth(xs, 0)
+}
+pub fn mpcf_snd<I>(xs: Vec<I>) -> I {
+    mpcf_nth(xs, 1)
+}
+pub fn mpcf_trd<I>(xs: Vec<I>) -> I {
+    mpcf_nth(xs, 2)
+}
+
+pub fn mpcf_freefold<I>(_xs: Vec<I>) {}
+pub fn mpcf_strfold(xs: Vec<String>) -> String {
+    xs.concat()
+}
+
+// Regular Expression Parsers
+
+// Flags for `mpc_re_mode`, the long names meaning the same as the short ones.
+pub enum MPC {
+    RE_DEFAULT = 0,
+    RE_M = 1,
+    RE_S = 2,
+    RE_MULTILINE = 4,
+    RE_DOTALL = 8,
+}
+
+pub fn mpc_re(re: &str) -> Parser<String> {
+    mpc_re_mode(re, MPC::RE_DEFAULT as i32)
+}
+
+// Like the rest of the library regular expressions don't backtrack, so
+// repetition is greedy and never gives anything back: /a*a/ matches nothing.
+// A regular expression which doesn't itself parse gives a parser which fails.
+pub fn mpc_re_mode(re: &str, mode: i32) -> Parser<String> {
+    match mpc_parse("<mpc_re_compiler>", re, &mpc_re_grammar()) {
+        Ok(a) => mpc_expectf(&mpc_re_build(&a, mode), format_args!("/{re}/")),
+        Err(e) => mpc_failf(format_args!(
+            "Invalid Regex /{re}/: {}",
+            mpc_err_message(&e)
+        )),
+    }
+}
+
+// Parses a regular expression into an AST of alternatives of terms of
+// factors, later built into a parser by `mpc_re_build`.
+fn mpc_re_grammar() -> Parser<mpc_ast_t> {
+    let regex: Parser<mpc_ast_t> = mpc_new("regex");
+
+    let escaped = mpc_and2(|_, c| c, &mpc_char('\\'), &mpc_any());
+    let group = mpc_and3(|_, r, _| r, &mpc_char('('), &regex, &mpc_char(')'));
+    let range = mpc_many(
+        mpcf_strfold,
+        &mpc_or(&[
+            &mpc_and(mpcf_strfold, &[&mpc_char('\\'), &mpc_any()]),
+            &mpc_noneof("]"),
+        ]),
+    );
+    let base = mpc_or(&[
+        &mpc_ast_leaf(&mpc_oneof("^$."), "special"),
+        &group,
+        &mpc_ast_leaf(&mpc_squares(&range), "range"),
+        &mpc_ast_leaf(&escaped, "escape"),
+        &mpc_ast_leaf(&mpc_noneof(")|"), "char"),
+    ]);
+
+    let repeat = mpc_or(&[
+        &mpc_ast_leaf(&mpc_oneof("*+?"), "repeat"),
+        &mpc_ast_leaf(&mpc_brackets(&mpc_digits()), "count"),
+    ]);
+    let factor = mpc_and2(
+        |b, r: Option<mpc_ast_t>| match r {
+            Some(r) => mpc_ast_build("factor", vec![b, r]),
+            None => b,
+        },
+        &base,
+        &mpc_maybe(&repeat),
+    );
+
+    let term = mpc_many(|xs| mpc_ast_build("term", xs), &factor);
+    let terms = mpc_many(|xs| xs, &mpc_and2(|_, t| t, &mpc_char('|'), &term));
+    mpc_define(
+        &regex,
+        &mpc_and2(
+            |t, mut ts: Vec<mpc_ast_t>| {
+                ts.insert(0, t);
+                mpc_ast_build("regex", ts)
+            },
+            &term,
+            &terms,
+        ),
+    );
+
+    mpc_whole(&regex)
+}
+
+fn mpc_re_bol_anchor(prev: Option<char>, _next: Option<char>) -> bool {
+    prev.is_none() || prev == Some('\n')
+}
+
+fn mpc_re_eol_anchor(_prev: Option<char>, next: Option<char>) -> bool {
+    next.is_none() || next == Some('\n')
+}
+
+fn mpc_re_empty(a: &Parser<()>) -> Parser<String> {
+    mpc_apply(a, |_| String::new())
+}
+
+fn mpc_re_except(a: &Parser<String>) -> Parser<String> {
+    mpc_and2(|_, c| c, &mpc_not(a), &mpc_any())
+}
+
+fn mpc_re_unescape(c: char) -> char {
+    match c {
+        'a' => '\x07',
+        'b' => '\x08',
+        'f' => '\x0c',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        'v' => '\x0b',
+        '0' => '\0',
+        c => c,
+    }
+}
+
+fn mpc_re_escape(c: char) -> Parser<String> {
+    match c {
+        'd' => mpc_digit(),
+        'D' => mpc_re_except(&mpc_digit()),
+        's' => mpc_whitespace(),
+        'S' => mpc_re_except(&mpc_whitespace()),
+        'w' => mpc_alphanum(),
+        'W' => mpc_re_except(&mpc_alphanum()),
+        'A' => mpc_re_empty(&mpc_soi()),
+        'Z' => mpc_re_empty(&mpc_eoi()),
+        'b' => mpc_re_empty(&mpc_boundary()),
+        'B' => mpc_re_empty(&mpc_not(&mpc_boundary())),
+        c => mpc_char(mpc_re_unescape(c)),
+    }
+}
+
+// The inside of a [...] class, with ranges like a-z and a leading ^ to negate.
+fn mpc_re_range(s: &str) -> Parser<String> {
+    let (negate, s) = match s.strip_prefix('^') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+
+    let mut cs = s.chars().peekable();
+    let next = |cs: &mut std::iter::Peekable<std::str::Chars>| match cs.next() {
+        Some('\\') => cs.next().map(mpc_re_unescape),
+        c => c,
+    };
+
+    let mut single = String::new();
+    let mut ranges = vec![];
+    while let Some(c) = next(&mut cs) {
+        let mut ahead = cs.clone();
+        match (ahead.next(), ahead.peek()) {
+            (Some('-'), Some(_)) => {
+                cs.next();
+                let d = next(&mut cs).unwrap_or(c);
+                ranges.push(mpc_range(c, d));
+            }
+            _ => single.push(c),
+        }
+    }
+
+    if ranges.is_empty() && negate {
+        return mpc_noneof(&single);
+    }
+    if ranges.is_empty() {
+        return mpc_oneof(&single);
+    }
+
+    let single = mpc_oneof(&single);
+    let mut ps: Vec<&Parser<String>> = ranges.iter().collect();
+    ps.push(&single);
+    let p = mpc_or(&ps);
+    if negate {
+        mpc_re_except(&p)
+    } else {
+        p
+    }
+}
+
+fn mpc_re_build(a: &mpc_ast_t, mode: i32) -> Parser<String> {
+    let multiline = mode & (MPC::RE_M as i32 | MPC::RE_MULTILINE as i32) != 0;
+    let dotall = mode & (MPC::RE_S as i32 | MPC::RE_DOTALL as i32) != 0;
+    let first = || a.contents.chars().next().unwrap_or_default();
+
+    match a.tag.as_str() {
+        "regex" | "term" => {
+            let ps: Vec<Parser<String>> =
+                a.children.iter().map(|c| mpc_re_build(c, mode)).collect();
+            let ps: Vec<&Parser<String>> = ps.iter().collect();
+            match (a.tag.as_str(), ps.as_slice()) {
+                (_, [p]) => (*p).clone(),
+                ("regex", ps) => mpc_or(ps),
+                (_, []) => mpc_lift(mpcf_ctor_str),
+                (_, ps) => mpc_and(mpcf_strfold, ps),
+            }
+        }
+        "factor" => {
+            let p = mpc_re_build(&a.children[0], mode);
+            let r = &a.children[1];
+            match (r.tag.as_str(), r.contents.as_str()) {
+                ("repeat", "*") => mpc_many(mpcf_strfold, &p),
+                ("repeat", "+") => mpc_many1(mpcf_strfold, &p),
+                ("repeat", _) => mpc_maybe_lift(&p, mpcf_ctor_str),
+                (_, n) => mpc_count(n.parse().unwrap_or(0), mpcf_strfold, &p),
+            }
+        }
+        "special" => match first() {
+            '^' if multiline => mpc_re_empty(&mpc_anchor(mpc_re_bol_anchor)),
+            '^' => mpc_re_empty(&mpc_soi()),
+            '$' if multiline => mpc_re_empty(&mpc_anchor(mpc_re_eol_anchor)),
+            '$' => mpc_re_empty(&mpc_eoi()),
+            _ if dotall => mpc_any(),
+            _ => mpc_noneof("\n"),
+        },
+        "range" => mpc_re_range(&a.contents),
+        "escape" => mpc_re_escape(first()),
+        _ => mpc_char(first()),
+    }
+}
+
+// AST
+#[derive(Debug, Clone)]
+pub struct mpc_ast_t {
+    pub tag: String,
+    pub contents: String,
+    pub state: mpc_state_t,
+    pub children: Vec<mpc_ast_t>,
+}
+
+pub fn mpc_ast_new(tag: &str, contents: &str) -> mpc_ast_t {
+    mpc_ast_t {
+        tag: tag.to_owned(),
+        contents: contents.to_owned(),
+        state: mpc_state_new(),
+        children: vec![],
+    }
+}
+
+// Stands in for C's NULL node: no tag, no contents and no children.
+fn mpc_ast_null() -> mpc_ast_t {
+    mpc_ast_new("", "")
+}
+
+fn mpc_ast_is_null(a: &mpc_ast_t) -> bool {
+    a.tag.is_empty() && a.contents.is_empty() && a.children.is_empty()
+}
+
+pub fn mpc_ast_build(tag: &str, children: Vec<mpc_ast_t>) -> mpc_ast_t {
+    let mut a = mpc_ast_new(tag, "");
+    a.children = children;
+    a
+}
+
+pub fn mpc_ast_add_root(a: mpc_ast_t) -> mpc_ast_t {
+    if a.children.len() <= 1 {
+        return a;
+    }
+    mpc_ast_add_child(mpc_ast_new(">", ""), a)
+}
+
+pub fn mpc_ast_add_child(mut r: mpc_ast_t, a: mpc_ast_t) -> mpc_ast_t {
+    r.children.push(a);
+    r
+}
+
+pub fn mpc_ast_add_tag(mut a: mpc_ast_t, t: &str) -> mpc_ast_t {
+    if mpc_ast_is_null(&a) {
+        return a;
+    }
+    a.tag = format!("{t}|{}", a.tag);
+    a
+}
+
+pub fn mpc_ast_add_root_tag(mut a: mpc_ast_t, t: &str) -> mpc_ast_t {
+    if mpc_ast_is_null(&a) {
+        return a;
+    }
+    a.tag = format!("{t}{}", a.tag);
+    a
+}
+
+pub fn mpc_ast_tag(mut a: mpc_ast_t, t: &str) -> mpc_ast_t {
+    if mpc_ast_is_null(&a) {
+        return a;
+    }
+    a.tag = t.to_owned();
+    a
+}
+
+pub fn mpc_ast_state(mut a: mpc_ast_t, s: mpc_state_t) -> mpc_ast_t {
+    a.state = s;
+    a
+}
+
+pub fn mpc_ast_delete(a: mpc_ast_t) {
+    drop(a)
+}
+
+fn mpc_ast_print_depth(a: &mpc_ast_t, d: usize, out: &mut String) {
+    out.push_str(&"  ".repeat(d));
+
+    if !a.contents.is_empty() {
+        out.push_str(&format!(
+            "{}:{}:{} '{}'\n",
+            a.tag,
+            a.state.row + 1,
+            a.state.col + 1,
+            a.contents
+        ));
+    } else {
+        out.push_str(&format!("{} \n", a.tag));
+    }
+
+    for c in &a.children {
+        mpc_ast_print_depth(c, d + 1, out);
+    }
+}
+
+pub fn mpc_ast_print(a: &mpc_ast_t) {
+    let mut out = String::new();
+    mpc_ast_print_depth(a, 0, &mut out);
+    print!("{out}");
+}
+
+pub fn mpc_ast_print_to(a: &mpc_ast_t, f: &mut File) {
+    let mut out = String::new();
+    mpc_ast_print_depth(a, 0, &mut out);
+    match write!(f, "{out}") {
+        Ok(_) => (),
+        Err(e) => panic!("Error: {e} occured."),
+    }
+}
+
+pub fn mpc_ast_get_index(ast: &mpc_ast_t, tag: &str) -> i32 {
+    mpc_ast_get_index_lb(ast, tag, 0)
+}
+pub fn mpc_ast_get_index_lb(ast: &mpc_ast_t, tag: &str, lb: i32) -> i32 {
+    let lb = lb.max(0) as usize;
+    match ast.children.iter().skip(lb).position(|c| c.tag == tag) {
+        Some(k) => (lb + k) as i32,
+        None => -1,
+    }
+}
+
+pub fn mpc_ast_get_child<'a>(ast: &'a mpc_ast_t, tag: &str) -> Option<&'a mpc_ast_t> {
+    mpc_ast_get_child_lb(ast, tag, 0)
+}
+pub fn mpc_ast_get_child_lb<'a>(ast: &'a mpc_ast_t, tag: &str, lb: i32) -> Option<&'a mpc_ast_t> {
+    match mpc_ast_get_index_lb(ast, tag, lb) {
+        -1 => None,
+        k => Some(&ast.children[k as usize]),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum mpc_ast_trav_order_t {
+    mpc_ast_trav_order_pre,
+    mpc_ast_trav_order_post,
+}
+
+// Each frame holds a node and the index of the next child to visit.
+pub struct mpc_ast_trav_t<'a> {
+    stack: Vec<(&'a mpc_ast_t, usize)>,
+    order: mpc_ast_trav_order_t,
+}
+
+pub fn mpc_ast_traverse_start(ast: &mpc_ast_t, order: mpc_ast_trav_order_t) -> mpc_ast_trav_t<'_> {
+    mpc_ast_trav_t {
+        stack: vec![(ast, 0)],
+        order,
+    }
+}
+
+pub fn mpc_ast_traverse_next<'a>(trav: &mut mpc_ast_trav_t<'a>) -> Option<&'a mpc_ast_t> {
+    match trav.order {
+        mpc_ast_trav_order_t::mpc_ast_trav_order_pre => {
+            let (a, _) = trav.stack.pop()?;
+            for c in a.children.iter().rev() {
+                trav.stack.push((c, 0));
+            }
+            Some(a)
+        }
+        mpc_ast_trav_order_t::mpc_ast_trav_order_post => loop {
+            let (a, k) = trav.stack.last_mut()?;
+            let a: &'a mpc_ast_t = a;
+            if *k < a.children.len() {
+                *k += 1;
+                let c = &a.children[*k - 1];
+                trav.stack.push((c, 0));
+            } else {
+                trav.stack.pop();
+                return Some(a);
+            }
+        },
+    }
+}
+
+pub fn mpc_ast_traverse_free(trav: mpc_ast_trav_t) {
+    drop(trav)
+}
 
 // Warning: This function currently doesn't test for equality of the `state` member!
-fn mpc_ast_eq(a: mpc_ast_t, b: mpc_ast_t) -> i32 {
-    unimplemented!()
+pub fn mpc_ast_eq(a: &mpc_ast_t, b: &mpc_ast_t) -> bool {
+    a.tag == b.tag
+        && a.contents == b.contents
+        && a.children.len() == b.children.len()
+        && a.children
+            .iter()
+            .zip(&b.children)
+            .all(|(x, y)| mpc_ast_eq(x, y))
 }
 
-fn mpcf_fold_ast(n: i32, xs: Vec<Option<Box<mpc_val_t>>>) -> mpc_val_t {
-    unimplemented!()
+pub fn mpcf_fold_ast(mut xs: Vec<mpc_ast_t>) -> mpc_ast_t {
+    let n = xs.len();
+
+    if n == 0 {
+        return mpc_ast_null();
+    }
+    if n == 1 || (n == 2 && mpc_ast_is_null(&xs[1])) {
+        return xs.swap_remove(0);
+    }
+    if n == 2 && mpc_ast_is_null(&xs[0]) {
+        return xs.swap_remove(1);
+    }
+
+    let mut r = mpc_ast_new(">", "");
+    for a in xs.into_iter().filter(|a| !mpc_ast_is_null(a)) {
+        if a.children.is_empty() {
+            r.children.push(a);
+        } else {
+            r.children.extend(a.children);
+        }
+    }
+
+    if let Some(c) = r.children.first() {
+        r.state = c.state;
+    }
+
+    r
 }
-fn mpcf_str_ast(c: Option<Box<mpc_val_t>>) -> mpc_val_t {
-    unimplemented!()
+pub fn mpcf_str_ast(c: String) -> mpc_ast_t {
+    mpc_ast_new("", &c)
 }
-fn mpcf_state_ast(n: i32, xs: Vec<Option<Box<mpc_val_t>>>) -> mpc_val_t {
-    unimplemented!()
+pub fn mpcf_state_ast(s: mpc_state_t, a: mpc_ast_t) -> mpc_ast_t {
+    mpc_ast_state(a, s)
 }
 
-fn mpca_tag(a: &mpc_parser_t, t: &str) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpca_tag(a: &Parser<mpc_ast_t>, t: &str) -> Parser<mpc_ast_t> {
+    mpc_apply_to(a, |a, t: &String| mpc_ast_tag(a, t), t.to_owned())
+}
+pub fn mpca_add_tag(a: &Parser<mpc_ast_t>, t: &str) -> Parser<mpc_ast_t> {
+    mpc_apply_to(a, |a, t: &String| mpc_ast_add_tag(a, t), t.to_owned())
 }
-fn mpca_add_tag(a: &mpc_parser_t, t: &str) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpca_root(a: &Parser<mpc_ast_t>) -> Parser<mpc_ast_t> {
+    mpc_apply(a, mpc_ast_add_root)
 }
-fn mpca_root(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpca_state(a: &Parser<mpc_ast_t>) -> Parser<mpc_ast_t> {
+    mpc_and2(mpcf_state_ast, &mpc_state(), a)
 }
-fn mpca_state(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+// On failure skips to `sync`, leaving an "error" node holding what was skipped.
+pub fn mpca_recover<S>(a: &Parser<mpc_ast_t>, sync: &Parser<S>) -> Parser<mpc_ast_t> {
+    let skip = mpc_apply(&mpc_skip_until(sync), |s| mpc_ast_new("error", &s));
+    mpc_recover(a, &mpca_state(&skip))
 }
-fn mpca_total(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpca_total(a: &Parser<mpc_ast_t>) -> Parser<mpc_ast_t> {
+    mpc_total(a)
 }
 
-fn mpca_not(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpca_not(a: &Parser<mpc_ast_t>) -> Parser<mpc_ast_t> {
+    mpc_not_lift(a, mpc_ast_null)
 }
-fn mpca_maybe(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpca_maybe(a: &Parser<mpc_ast_t>) -> Parser<mpc_ast_t> {
+    mpc_maybe_lift(a, mpc_ast_null)
 }
 
-fn mpca_many(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpca_many(a: &Parser<mpc_ast_t>) -> Parser<mpc_ast_t> {
+    mpc_many(mpcf_fold_ast, a)
 }
-fn mpca_many1(a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpca_many1(a: &Parser<mpc_ast_t>) -> Parser<mpc_ast_t> {
+    mpc_many1(mpcf_fold_ast, a)
 }
-fn mpca_count(n: i32, a: &mpc_parser_t) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpca_count(n: usize, a: &Parser<mpc_ast_t>) -> Parser<mpc_ast_t> {
+    mpc_count(n, mpcf_fold_ast, a)
 }
 
-fn mpca_or(n: i32, args: Arguments) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpca_or(ps: &[&Parser<mpc_ast_t>]) -> Parser<mpc_ast_t> {
+    mpc_or(ps)
 }
-fn mpca_and(n: i32, args: Arguments) -> mpc_parser_t {
-    unimplemented!()
+pub fn mpca_and(ps: &[&Parser<mpc_ast_t>]) -> Parser<mpc_ast_t> {
+    mpc_and(mpcf_fold_ast, ps)
 }
 
-enum MPCA {
+pub enum MPCA {
     LANG_DEFAULT = 0,
     LANG_PREDICTIVE = 1,
     LANG_WHITESPACE_SENSITIVE = 2,
 }
 
-fn mpca_grammer(flags: i32, grammer: &str, args: Arguments) -> mpc_parser_t {
-    unimplemented!()
+fn mpc_ast_leaf(a: &Parser<String>, tag: &str) -> Parser<mpc_ast_t> {
+    mpc_apply_to(a, |s, t: &String| mpc_ast_new(t, &s), tag.to_owned())
+}
+
+// Parses the grammar language into an AST of statements, each holding a name,
+// an optional description and a grammar of alternatives of terms of factors.
+fn mpca_lang_grammar() -> (Parser<mpc_ast_t>, Parser<mpc_ast_t>) {
+    let grammar: Parser<mpc_ast_t> = mpc_new("grammar");
+
+    let ident = mpc_many1(mpcf_strfold, &mpc_alphanum());
+    let flags = mpc_many(mpcf_strfold, &mpc_oneof("ms"));
+    let regex = mpc_and2(
+        |r, f| mpc_ast_build("regex", vec![r, f]),
+        &mpc_ast_leaf(&mpc_regex_lit(), ""),
+        &mpc_ast_leaf(&flags, ""),
+    );
+    let base = mpc_or(&[
+        &mpc_ast_leaf(&mpc_tok(&mpc_string_lit()), "string"),
+        &mpc_ast_leaf(&mpc_tok(&mpc_char_lit()), "char"),
+        &mpc_tok(&regex),
+        &mpc_ast_leaf(&mpc_tok(&mpc_braces(&ident)), "ref"),
+        &mpc_tok(&mpc_between(&mpc_strip(&grammar), "(", ")")),
+    ]);
+
+    let repeat = mpc_or(&[
+        &mpc_ast_leaf(&mpc_tok(&mpc_oneof("*+?!")), "repeat"),
+        &mpc_ast_leaf(&mpc_tok(&mpc_brackets(&mpc_digits())), "count"),
+    ]);
+    let factor = mpc_and2(
+        |b, r: Option<mpc_ast_t>| match r {
+            Some(r) => mpc_ast_build("factor", vec![b, r]),
+            None => b,
+        },
+        &base,
+        &mpc_maybe(&repeat),
+    );
+
+    let term = mpc_many(|xs| mpc_ast_build("term", xs), &factor);
+    let terms = mpc_many(|xs| xs, &mpc_and2(|_, t| t, &mpc_sym("|"), &term));
+    mpc_define(
+        &grammar,
+        &mpc_and2(
+            |t, mut ts: Vec<mpc_ast_t>| {
+                ts.insert(0, t);
+                mpc_ast_build("grammar", ts)
+            },
+            &term,
+            &terms,
+        ),
+    );
+
+    let name = mpca_state(&mpc_ast_leaf(&mpc_tok(&ident), "name"));
+    let desc = mpc_maybe(&mpc_ast_leaf(&mpc_tok(&mpc_string_lit()), "desc"));
+    let stmt = mpc_and(
+        |mut xs: Vec<mpc_ast_t>| {
+            xs.retain(|x| !x.tag.is_empty());
+            mpc_ast_build("stmt", xs)
+        },
+        &[
+            &name,
+            &mpc_apply(&desc, |d| d.unwrap_or_else(|| mpc_ast_new("", ""))),
+            &mpc_ast_leaf(&mpc_sym(":"), ""),
+            &grammar,
+            &mpc_ast_leaf(&mpc_sym(";"), ""),
+        ],
+    );
+
+    let lang = mpc_many(|xs| mpc_ast_build("lang", xs), &stmt);
+    (mpc_total(&grammar), mpc_total(&lang))
+}
+
+fn mpca_lang_find(x: &str, ps: &[&Parser<mpc_ast_t>]) -> Parser<mpc_ast_t> {
+    match x.parse::<usize>() {
+        Ok(k) if k < ps.len() => ps[k].clone(),
+        Ok(k) => mpc_failf(format_args!(
+            "No Parser in position {k}! Only supplied {} Parsers!",
+            ps.len()
+        )),
+        Err(_) => match ps.iter().find(|p| p.name.as_deref() == Some(x)) {
+            Some(p) => (*p).clone(),
+            None => mpc_failf(format_args!("Unknown Parser '{x}'!")),
+        },
+    }
 }
-fn mpca_lang(flags: i32, language: &str, args: Arguments) -> mpc_parser_t {
-    unimplemented!()
+
+fn mpca_lang_build(a: &mpc_ast_t, flags: i32, ps: &[&Parser<mpc_ast_t>]) -> Parser<mpc_ast_t> {
+    let tok = |p: Parser<String>| match flags & MPCA::LANG_WHITESPACE_SENSITIVE as i32 {
+        0 => mpc_tok(&p),
+        _ => p,
+    };
+
+    match a.tag.as_str() {
+        "grammar" | "term" => {
+            let xs: Vec<Parser<mpc_ast_t>> = a
+                .children
+                .iter()
+                .map(|c| mpca_lang_build(c, flags, ps))
+                .collect();
+            let xs: Vec<&Parser<mpc_ast_t>> = xs.iter().collect();
+            match (a.tag.as_str(), xs.as_slice()) {
+                (_, [x]) => (*x).clone(),
+                ("grammar", xs) => mpca_or(xs),
+                (_, []) => mpc_lift(mpc_ast_null),
+                (_, xs) => mpca_and(xs),
+            }
+        }
+        "factor" => {
+            let x = mpca_lang_build(&a.children[0], flags, ps);
+            let r = &a.children[1];
+            match (r.tag.as_str(), r.contents.as_str()) {
+                ("repeat", "*") => mpca_many(&x),
+                ("repeat", "+") => mpca_many1(&x),
+                ("repeat", "?") => mpca_maybe(&x),
+                ("repeat", _) => mpca_not(&x),
+                (_, n) => mpca_count(n.parse().unwrap_or(0), &x),
+            }
+        }
+        "string" => {
+            let x = tok(mpc_string(&mpcf_unescape(a.contents.clone())));
+            mpca_state(&mpca_tag(&mpc_apply(&x, mpcf_str_ast), "string"))
+        }
+        "char" => {
+            let c = mpcf_unescape(a.contents.clone());
+            let x = tok(mpc_char(c.chars().next().unwrap_or_default()));
+            mpca_state(&mpca_tag(&mpc_apply(&x, mpcf_str_ast), "char"))
+        }
+        "regex" => {
+            let mut mode = MPC::RE_DEFAULT as i32;
+            for f in a.children[1].contents.chars() {
+                mode |= match f {
+                    'm' => MPC::RE_M as i32,
+                    _ => MPC::RE_S as i32,
+                };
+            }
+            let re = mpcf_unescape_regex(a.children[0].contents.clone());
+            let x = tok(mpc_re_mode(&re, mode));
+            mpca_state(&mpca_tag(&mpc_apply(&x, mpcf_str_ast), "regex"))
+        }
+        _ => {
+            let x = mpca_lang_find(&a.contents, ps);
+            let name = x.name.clone().unwrap_or_else(|| a.contents.clone());
+            mpca_state(&mpca_root(&mpca_add_tag(&x, &name)))
+        }
+    }
 }
-fn mpca_lang_file(flags: i32, f: &mut File, args: Arguments) -> mpc_parser_t {
-    unimplemented!()
+
+// Defines each parser named by a statement, the rest are left alone.
+fn mpca_lang_define(
+    flags: i32,
+    filename: &str,
+    r: mpc_result_t<mpc_ast_t>,
+    ps: &[&Parser<mpc_ast_t>],
+) -> Result<(), mpc_err_t> {
+    for stmt in &r?.children {
+        let name = &stmt.children[0];
+        let p = match ps
+            .iter()
+            .find(|p| p.name.as_deref() == Some(&*name.contents))
+        {
+            Some(p) => p,
+            None => {
+                return Err(mpc_err_t {
+                    state: name.state,
+                    ..mpc_err_file(filename, &format!("Unknown Parser '{}'!", name.contents))
+                })
+            }
+        };
+
+        let mut x = mpca_lang_build(stmt.children.last().unwrap(), flags, ps);
+        if flags & MPCA::LANG_PREDICTIVE as i32 != 0 {
+            x = mpc_predictive(&x);
+        }
+        x = match stmt.children.len() {
+            3 => mpc_expect(&x, &mpcf_unescape(stmt.children[1].contents.clone())),
+            _ => mpc_expect(&x, &name.contents),
+        };
+        mpc_define(p, &x);
+    }
+    Ok(())
 }
-fn mpca_lang_pipe(flags: i32, f: &mut File, args: Arguments) -> mpc_parser_t {
-    unimplemented!()
+
+// A single grammar expression, referring to the parsers `ps` by name or index.
+pub fn mpca_grammer(flags: i32, grammer: &str, ps: &[&Parser<mpc_ast_t>]) -> Parser<mpc_ast_t> {
+    match mpc_parse("<mpca_grammar>", grammer, &mpca_lang_grammar().0) {
+        Ok(a) => {
+            let x = mpca_lang_build(&a, flags, ps);
+            match flags & MPCA::LANG_PREDICTIVE as i32 {
+                0 => x,
+                _ => mpc_predictive(&x),
+            }
+        }
+        Err(e) => mpc_failf(format_args!("Invalid Grammar: {}", mpc_err_message(&e))),
+    }
 }
-fn mpca_lang_contents(flags: i32, filename: &str, args: Arguments) -> mpc_parser_t {
-    unimplemented!()
+
+// Defines each of `ps` from statements like `name "description" : grammar ;`.
+// Rules may refer to themselves on the left, as in `expr : <expr> '+' <term>`.
+pub fn mpca_lang(flags: i32, language: &str, ps: &[&Parser<mpc_ast_t>]) -> Result<(), mpc_err_t> {
+    let r = mpc_parse("<mpca_lang>", language, &mpca_lang_grammar().1);
+    mpca_lang_define(flags, "<mpca_lang>", r, ps)
+}
+pub fn mpca_lang_file<R: Read + Seek>(
+    flags: i32,
+    f: R,
+    ps: &[&Parser<mpc_ast_t>],
+) -> Result<(), mpc_err_t> {
+    let r = mpc_parse_file("<mpca_lang_file>", f, &mpca_lang_grammar().1);
+    mpca_lang_define(flags, "<mpca_lang_file>", r, ps)
+}
+pub fn mpca_lang_pipe<R: Read>(
+    flags: i32,
+    f: R,
+    ps: &[&Parser<mpc_ast_t>],
+) -> Result<(), mpc_err_t> {
+    let r = mpc_parse_pipe("<mpca_lang_pipe>", f, &mpca_lang_grammar().1);
+    mpca_lang_define(flags, "<mpca_lang_pipe>", r, ps)
+}
+pub fn mpca_lang_contents(
+    flags: i32,
+    filename: &str,
+    ps: &[&Parser<mpc_ast_t>],
+) -> Result<(), mpc_err_t> {
+    let r = mpc_parse_contents(filename, &mpca_lang_grammar().1);
+    mpca_lang_define(flags, filename, r, ps)
 }
 
 // Misc
 
-fn mpc_print(p: &mpc_parser_t) {}
-fn mpc_optimise(p: &mpc_parser_t) {}
-fn mpc_stats(p: &mpc_parser_t) {}
+fn mpc_print_unretained(p: &mpc_parser_t, force: bool, out: &mut String) {
+    if p.retained && !force {
+        match &p.name {
+            Some(name) => out.push_str(&format!("<{name}>")),
+            None => out.push_str("<anon>"),
+        }
+        return;
+    }
 
-fn mpc_test_pass(
-    p: &mpc_parser_t,
+    let esc = |s: &str| mpcf_escape_new(s, &MPC_ESCAPE_INPUT_C, &MPC_ESCAPE_OUTPUT_C);
+    let child = |out: &mut String, k: usize| {
+        if let Some(c) = p.children.get(k) {
+            mpc_print_unretained(c, false, out);
+        }
+    };
+    let list = |out: &mut String, sep: &str| {
+        out.push('(');
+        for (k, c) in p.children.iter().enumerate() {
+            if k > 0 {
+                out.push_str(sep);
+            }
+            mpc_print_unretained(c, false, out);
+        }
+        out.push(')');
+    };
+
+    match &p.kind {
+        mpc_type_t::NAMED(d, _) => match &*d.borrow() {
+            Some(d) => mpc_print_unretained(d, false, out),
+            None => out.push_str("<?>"),
+        },
+        mpc_type_t::PASS => out.push_str("<:>"),
+        mpc_type_t::FAIL(..) => out.push_str("<!>"),
+        mpc_type_t::LIFT(_) => out.push_str("<#>"),
+        mpc_type_t::STATE => out.push_str("<S>"),
+        mpc_type_t::ANCHOR(_) => out.push_str("<@>"),
+        mpc_type_t::ANY => out.push_str("<.>"),
+        mpc_type_t::SATISFY(_) => out.push_str("<f>"),
+        mpc_type_t::SINGLE(c) => out.push_str(&format!("'{}'", esc(&c.to_string()))),
+        mpc_type_t::RANGE(c, d) => out.push_str(&format!(
+            "[{}-{}]",
+            esc(&c.to_string()),
+            esc(&d.to_string())
+        )),
+        mpc_type_t::ONEOF(s) => out.push_str(&format!("[{}]", esc(s))),
+        mpc_type_t::NONEOF(s) => out.push_str(&format!("[^{}]", esc(s))),
+        mpc_type_t::STRING(s) => out.push_str(&format!("\"{}\"", esc(s))),
+        mpc_type_t::STRING_NOCASE(s) => out.push_str(&format!("\"{}\"i", esc(s))),
+        mpc_type_t::STRING_ONEOF(ss) => {
+            let ss: Vec<String> = ss.iter().map(|s| format!("\"{}\"", esc(s))).collect();
+            out.push_str(&format!("({})", ss.join(" | ")));
+        }
+        mpc_type_t::ANY_BYTE => out.push_str("<b>"),
+        mpc_type_t::BYTE_RANGE(c, d) if c == d => out.push_str(&format!("'\\x{c:02x}'")),
+        mpc_type_t::BYTE_RANGE(c, d) => out.push_str(&format!("[\\x{c:02x}-\\x{d:02x}]")),
+        mpc_type_t::APPLY(_) | mpc_type_t::PREDICT | mpc_type_t::SPAN => child(out, 0),
+        mpc_type_t::NOT(_) => {
+            child(out, 0);
+            out.push('!');
+        }
+        mpc_type_t::MAYBE(_) => {
+            child(out, 0);
+            out.push('?');
+        }
+        mpc_type_t::MANY(_) => {
+            child(out, 0);
+            out.push('*');
+        }
+        mpc_type_t::MANY1(_) => {
+            child(out, 0);
+            out.push('+');
+        }
+        mpc_type_t::COUNT(n, _) => {
+            child(out, 0);
+            out.push_str(&format!("{{{n}}}"));
+        }
+        mpc_type_t::BLOCK => {
+            out.push_str("<b>{");
+            child(out, 0);
+            out.push('}');
+        }
+        mpc_type_t::OR => list(out, " | "),
+        mpc_type_t::AND(_) => list(out, " "),
+        mpc_type_t::CHECK(..) => {
+            child(out, 0);
+            out.push_str("->?");
+        }
+        mpc_type_t::EXPECT(m, _) => out.push_str(m),
+        mpc_type_t::RECOVER => list(out, " ~ "),
+    }
+}
+
+fn mpc_nodecount_unretained(p: &mpc_parser_t, force: bool) -> usize {
+    if p.retained && !force {
+        return 1;
+    }
+    1 + mpc_edges(p)
+        .iter()
+        .map(|c| mpc_nodecount_unretained(c, false))
+        .sum::<usize>()
+}
+
+pub fn mpc_print(p: &mpc_parser_t) {
+    let mut out = String::new();
+    mpc_print_unretained(p, true, &mut out);
+    println!("{out}");
+}
+pub fn mpc_optimise(_p: &mpc_parser_t) {}
+pub fn mpc_stats(p: &mpc_parser_t) {
+    println!("Stats");
+    println!("=====");
+    println!("Node Count: {}", mpc_nodecount_unretained(p, true));
+}
+
+pub fn mpc_test_pass<O: 'static>(
+    p: &Parser<O>,
     s: &str,
-    d: Option<Box<c_void>>,
-    tester: fn(*const c_void, *const c_void) -> i32,
-    destructor: mpc_dtor_t,
-    printer: fn(*const c_void),
-) -> i32 {
-    unimplemented!()
+    d: &O,
+    tester: fn(&O, &O) -> bool,
+    printer: fn(&O),
+) -> bool {
+    let mut i = mpc_input_new_string("<test>", s);
+    match mpc_parse_input(&mut i, p) {
+        Ok(x) => {
+            if tester(&x, d) {
+                true
+            } else {
+                print!("Got ");
+                printer(&x);
+                println!();
+                print!("Expected ");
+                printer(d);
+                println!();
+                false
+            }
+        }
+        Err(e) => {
+            mpc_err_print(&e);
+            false
+        }
+    }
 }
 
-fn mpc_test_fail(
-    p: &mpc_parser_t,
+pub fn mpc_test_fail<O: 'static>(
+    p: &Parser<O>,
     s: &str,
-    d: Option<Box<c_void>>,
-    tester: fn(*const c_void, *const c_void) -> i32,
-    destructor: mpc_dtor_t,
-    printer: fn(*const c_void),
-) -> i32 {
-    unimplemented!()
+    d: &O,
+    tester: fn(&O, &O) -> bool,
+) -> bool {
+    let mut i = mpc_input_new_string("<test>", s);
+    match mpc_parse_input(&mut i, p) {
+        Ok(x) => !tester(&x, d),
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combinators() {
+        let ab = mpc_and(mpcf_strfold, &[&mpc_char('a'), &mpc_string("bc")]);
+        let p = mpc_many1(mpcf_strfold, &mpc_or(&[&ab, &mpc_range('0', '9')]));
+        assert_eq!(mpc_parse("<test>", "abc7abc", &p).unwrap(), "abc7abc");
+        assert_eq!(mpc_parse("<test>", "12x", &p).unwrap(), "12");
+        assert!(mpc_parse("<test>", "x", &p).is_err());
+
+        let p = mpc_and(mpcf_strfold, &[&mpc_oneof("xy"), &mpc_noneof("xy")]);
+        assert_eq!(mpc_parse("<test>", "yz", &p).unwrap(), "yz");
+        assert!(mpc_parse("<test>", "yy", &p).is_err());
+    }
+
+    #[test]
+    fn combinator_graph() {
+        let a = mpc_char('a');
+        let p = mpc_or(&[&a, &mpc_char('b')]);
+        assert!(p.name.is_none());
+        assert_eq!(p.children.len(), 2);
+        assert!(Rc::ptr_eq(&p.children[0], &a.p));
+    }
+
+    #[test]
+    fn nested_predictive() {
+        let inner = mpc_predictive(&mpc_char('a'));
+        let p = mpc_predictive(&mpc_and(mpcf_strfold, &[&inner, &mpc_char('b')]));
+        assert_eq!(mpc_parse("<test>", "ab", &p).unwrap(), "ab");
+        assert!(mpc_parse("<test>", "ac", &p).is_err());
+
+        let p = mpc_or(&[&p, &mpc_string("ac")]);
+        assert!(mpc_parse("<test>", "ac", &p).is_err());
+    }
+
+    #[test]
+    fn typed_values() {
+        let num = mpc_apply(&mpc_digits(), |s: String| s.parse::<u64>().unwrap());
+        let nums = mpc_many1(|xs: Vec<u64>| xs, &mpc_tok(&num));
+        assert_eq!(
+            mpc_parse("<test>", "1 22 333", &nums).unwrap(),
+            [1, 22, 333]
+        );
+
+        let pair = mpc_and3(
+            |a: u64, _, b: Option<u64>| (a, b),
+            &num,
+            &mpc_char(','),
+            &mpc_maybe(&num),
+        );
+        assert_eq!(mpc_parse("<test>", "4,5", &pair).unwrap(), (4, Some(5)));
+        assert_eq!(mpc_parse("<test>", "4,", &pair).unwrap(), (4, None));
+        assert_eq!(mpc_parse("<test>", "17", &mpc_int()).unwrap(), 17);
+    }
+
+    fn temp_file(name: &str, contents: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("mpc-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn entry_points() {
+        let p = mpc_many1(mpcf_strfold, &mpc_alpha());
+        assert_eq!(mpc_parse("<test>", "abc1", &p).unwrap(), "abc");
+        assert_eq!(mpc_nparse("<test>", "abcdef", 2, &p).unwrap(), "ab");
+        let file = std::io::Cursor::new("xyz");
+        assert_eq!(mpc_parse_file("<test>", file, &p).unwrap(), "xyz");
+        let pipe: &[u8] = b"pq";
+        assert_eq!(mpc_parse_pipe("<test>", pipe, &p).unwrap(), "pq");
+
+        let path = temp_file("entry_points", b"hello world");
+        assert_eq!(mpc_parse_contents(&path, &p).unwrap(), "hello");
+        std::fs::remove_file(&path).unwrap();
+        let e = mpc_parse_contents(&path, &p).unwrap_err();
+        assert_eq!(mpc_err_failure(&e), Some("Unable to open file!"));
+        assert_eq!(mpc_err_filename(&e), path);
+    }
+
+    #[test]
+    fn utf8_input() {
+        let p = mpc_and2(
+            |s, st: mpc_state_t| (s, st.pos, st.col),
+            &mpc_many(mpcf_strfold, &mpc_noneof("x")),
+            &mpc_state(),
+        );
+        let want = ("héllo→".to_owned(), 9, 6);
+        assert_eq!(mpc_parse("<test>", "héllo→x", &p).unwrap(), want);
+        let file = std::io::Cursor::new("héllo→x");
+        assert_eq!(mpc_parse_file("<test>", file, &p).unwrap(), want);
+        let pipe: &[u8] = "héllo→x".as_bytes();
+        assert_eq!(mpc_parse_pipe("<test>", pipe, &p).unwrap(), want);
+
+        let e = mpc_parse("<test>", "añx", &mpc_string("añy")).unwrap_err();
+        assert_eq!(mpc_err_received(&e), Some('a'));
+        let p = mpc_and2(|x, _| x, &p, &mpc_eoi());
+        let e = mpc_parse_bytes("<test>", b"ab\xffc", &p).unwrap_err();
+        assert_eq!(mpc_err_failure(&e), Some("Invalid UTF-8 in input!"));
+        assert_eq!(mpc_err_state(&e).pos, 2);
+    }
+
+    #[test]
+    fn byte_parsers() {
+        let data = b"\x01\x02\x03\x04\x00\x03abcZ\xff";
+        let p = mpc_and(
+            |xs: Vec<u64>| xs,
+            &[
+                &mpc_apply(&mpc_be_u16(), u64::from),
+                &mpc_apply(&mpc_le_u16(), u64::from),
+                &mpc_apply(&mpc_be_u16_block(), |b| b.len() as u64),
+                &mpc_apply(&mpc_byte_range(b'A', b'Z'), u64::from),
+                &mpc_apply(&mpc_byte(0xff), u64::from),
+            ],
+        );
+        assert_eq!(
+            mpc_parse_bytes("<test>", data, &p).unwrap(),
+            [0x102, 0x403, 3, 90, 255]
+        );
+
+        let be = mpc_and2(|x, _| x, &mpc_be_u32(), &mpc_eoi());
+        let le = mpc_and2(|x, _| x, &mpc_le_u32(), &mpc_eoi());
+        let p = mpc_or(&[&be, &le]);
+        assert_eq!(mpc_parse_bytes("<test>", b"\0\0\0\x01", &p).unwrap(), 1);
+        assert!(mpc_parse_bytes("<test>", b"\0\0\x01", &p).is_err());
+
+        let text = mpc_and2(|a, b| (a, b), &mpc_string("hi"), &mpc_u8_block());
+        let want = ("hi".to_owned(), b"\xfe\xfd".to_vec());
+        assert_eq!(
+            mpc_parse_bytes("<test>", b"hi\x02\xfe\xfd", &text).unwrap(),
+            want
+        );
+    }
+
+    // Hands out `data` a few bytes at a time, then fails once with `error` if
+    // given.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        error: Option<std::io::ErrorKind>,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(3).min(self.data.len() - self.pos);
+            if n == 0 {
+                return match self.error.take() {
+                    Some(k) => Err(k.into()),
+                    None => Ok(0),
+                };
+            }
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    fn trickle(data: &str, error: Option<std::io::ErrorKind>) -> Trickle {
+        Trickle {
+            data: data.as_bytes().to_vec(),
+            pos: 0,
+            error,
+        }
+    }
+
+    #[test]
+    fn pipe_backtracking() {
+        let long = mpc_and(mpcf_strfold, &[&mpc_string("abcdefg"), &mpc_char('!')]);
+        let p = mpc_or(&[&long, &mpc_string("abcdefgh")]);
+        let pipe = trickle("abcdefgh", None);
+        assert_eq!(mpc_parse_pipe("<test>", pipe, &p).unwrap(), "abcdefgh");
+
+        let p = mpc_many(|xs: Vec<String>| xs, &mpc_sym("word"));
+        let mut i = mpc_input_new_pipe("<test>", trickle(&"word\n".repeat(1000), None));
+        assert_eq!(mpc_parse_input(&mut i, &p).unwrap().len(), 1000);
+        assert!(i.buffer.len() < 64);
+    }
+
+    #[test]
+    fn pipe_read_error() {
+        let p = mpc_many(mpcf_strfold, &mpc_alpha());
+        let pipe = trickle("abcdefg", Some(std::io::ErrorKind::ConnectionReset));
+        let e = mpc_parse_pipe("<test>", pipe, &p).unwrap_err();
+        let failure = mpc_err_failure(&e).unwrap();
+        assert!(failure.starts_with("Unable to read input:"), "{failure}");
+        assert_eq!(mpc_err_state(&e).pos, 7);
+
+        let pipe = trickle("abc", Some(std::io::ErrorKind::Interrupted));
+        assert_eq!(mpc_parse_pipe("<test>", pipe, &p).unwrap(), "abc");
+    }
+
+    // A file which can't seek, or can't read past `fail` bytes.
+    struct Broken {
+        file: std::io::Cursor<Vec<u8>>,
+        fail: u64,
+    }
+
+    impl Read for Broken {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.file.position() >= self.fail {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            self.file.read(buf)
+        }
+    }
+
+    impl Seek for Broken {
+        fn seek(&mut self, to: SeekFrom) -> std::io::Result<u64> {
+            match to {
+                SeekFrom::Current(0) => self.file.seek(to),
+                _ if self.fail == u64::MAX => Err(std::io::ErrorKind::Unsupported.into()),
+                _ => self.file.seek(to),
+            }
+        }
+    }
+
+    fn broken(data: &str, fail: u64) -> Broken {
+        Broken {
+            file: std::io::Cursor::new(data.as_bytes().to_vec()),
+            fail,
+        }
+    }
+
+    #[test]
+    fn file_sources() {
+        let p = mpc_or(&[&mpc_string("abx"), &mpc_string("abc")]);
+        let file = std::io::Cursor::new(b"abc".to_vec());
+        assert_eq!(mpc_parse_file("<test>", file, &p).unwrap(), "abc");
+        let file = std::io::BufReader::new(std::io::Cursor::new("abc"));
+        assert_eq!(mpc_parse_file("<test>", file, &p).unwrap(), "abc");
+
+        let e = mpc_parse_file("<test>", broken("abc", u64::MAX), &p).unwrap_err();
+        let failure = mpc_err_failure(&e).unwrap();
+        assert!(failure.starts_with("Unable to read input:"), "{failure}");
+
+        let e = mpc_parse_file("<test>", broken("abc", 2), &p).unwrap_err();
+        let failure = mpc_err_failure(&e).unwrap();
+        assert!(failure.starts_with("Unable to read input:"), "{failure}");
+        assert_eq!(mpc_err_state(&e).pos, 2);
+
+        let text = format!("{}abc", "x".repeat(10000));
+        let path = temp_file("file_sources", text.as_bytes());
+        let xs = mpc_many(mpcf_strfold, &mpc_char('x'));
+        let q = mpc_and(mpcf_strfold, &[&xs, &p]);
+        assert_eq!(mpc_parse_contents(&path, &q).unwrap(), text);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn large_files() {
+        let path = temp_file("large_files", b"");
+        let f = File::options().write(true).open(&path).unwrap();
+        f.set_len(i32::MAX as u64 + 1).unwrap();
+        let e = mpc_parse_contents(&path, &mpc_any()).unwrap_err();
+        assert_eq!(mpc_err_failure(&e), Some("File too large!"));
+        #[cfg(feature = "mmap")]
+        assert!(unsafe { mpc_input_new_mmap(&path, &f) }.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn mmap_input() {
+        let path = temp_file("mmap_input", "héllo\nwörld".as_bytes());
+        let f = File::open(&path).unwrap();
+        let mut i = unsafe { mpc_input_new_mmap(&path, &f) }.unwrap();
+        let word = mpc_many1(mpcf_strfold, &mpc_noneof("!\n"));
+        let p = mpc_or(&[
+            &mpc_and(mpcf_strfold, &[&word, &mpc_char('!')]),
+            &mpc_and(mpcf_strfold, &[&word, &mpc_char('\n'), &word]),
+        ]);
+        assert_eq!(mpc_parse_input(&mut i, &p).unwrap(), "héllo\nwörld");
+        let r = unsafe { mpc_parse_contents_mmap(&path, &p) };
+        assert_eq!(r.unwrap(), "héllo\nwörld");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn borrowed_input() {
+        let s = "let answer = 42;".to_owned();
+        let i = mpc_input_new_string("<test>", &s);
+        assert_eq!((*i.string).as_ref().as_ptr(), s.as_ptr());
+
+        let ident = mpc_span(&mpc_many1(mpcf_strfold, &mpc_alpha()));
+        let p = mpc_many1(|xs: Vec<mpc_span_t>| xs, &mpc_tok(&ident));
+        let spans = mpc_parse("<test>", &s, &p).unwrap();
+        let words: Vec<&str> = spans.iter().map(|x| mpc_span_str(&s, x)).collect();
+        assert_eq!(words, ["let", "answer"]);
+        assert_eq!(words[1].as_ptr(), s[4..].as_ptr());
+        assert_eq!((spans[1].start.col, spans[1].end.col), (4, 10));
+
+        let b = b"\x00\x01ab";
+        let p = mpc_and2(|_, x| x, &mpc_bytes(2), &mpc_span(&mpc_bytes(2)));
+        let x = mpc_parse_bytes("<test>", b, &p).unwrap();
+        assert_eq!(mpc_span_bytes(b, &x), b"ab");
+    }
+
+    #[test]
+    fn pool_allocation() {
+        let i = mpc_input_new_string("<test>", "");
+        let x = mpc_malloc(&i, 7u32);
+        let p = x.ptr;
+        assert!(x.pool.is_some());
+        drop(x);
+        let y = mpc_malloc(&i, String::from("reused"));
+        assert_eq!(y.ptr, p);
+        assert_eq!(mpc_export::<String>(y), "reused");
+        assert_eq!(
+            i.mem.as_ref().unwrap().mem_free.borrow().len(),
+            MPC_INPUT_MEM_NUM
+        );
+
+        let big = mpc_malloc(&i, [0u8; 100]);
+        assert!(big.pool.is_none());
+        let mut i = i;
+        mpc_input_pool_disable(&mut i);
+        assert!(mpc_malloc(&i, 7u32).pool.is_none());
+        drop(i);
+        assert_eq!(mpc_val_take::<[u8; 100]>(big), [0; 100]);
+    }
+
+    #[test]
+    fn pool_drops_values() {
+        let rc = Rc::new(());
+        let x = mpc_lift_val(Rc::downgrade(&rc));
+        let p = mpc_many(|xs: Vec<_>| xs, &mpc_and2(|_, x| x, &mpc_char('a'), &x));
+        let r = mpc_parse("<test>", "aaab", &p).unwrap();
+        assert_eq!(r.len(), 3);
+        assert_eq!(Rc::weak_count(&rc), 4);
+        drop(r);
+        assert!(mpc_parse("<test>", "aaab", &mpc_and2(|a, _| a, &p, &mpc_eoi())).is_err());
+        assert_eq!(Rc::weak_count(&rc), 1);
+    }
+
+    #[test]
+    fn string_matching() {
+        let p = mpc_or(&[&mpc_string("while"), &mpc_string("whale")]);
+        assert_eq!(mpc_parse("<test>", "whale", &p).unwrap(), "whale");
+        let e = mpc_parse("<test>", "whilst", &p).unwrap_err();
+        assert_eq!(mpc_err_state(&e).pos, 0);
+
+        let p = mpc_string_nocase("SeLeCt");
+        assert_eq!(mpc_parse("<test>", "select *", &p).unwrap(), "select");
+        assert!(mpc_parse("<test>", "selec", &p).is_err());
+
+        let p = mpc_string_oneof(&["in", "int", "i", "integer"]);
+        assert_eq!(mpc_parse("<test>", "integers", &p).unwrap(), "integer");
+        assert_eq!(mpc_parse("<test>", "intx", &p).unwrap(), "int");
+        assert!(mpc_parse("<test>", "x", &p).is_err());
+    }
+
+    #[test]
+    fn primitive_output() {
+        let p = mpc_and(
+            |xs: Vec<String>| xs,
+            &[
+                &mpc_any(),
+                &mpc_range('a', 'z'),
+                &mpc_satisfy(|c| c.is_ascii_digit()),
+                &mpc_oneof("xyz"),
+                &mpc_string("end"),
+            ],
+        );
+        let r = mpc_parse("<test>", "ék7yend", &p).unwrap();
+        assert_eq!(r, ["é", "k", "7", "y", "end"]);
+
+        let p = mpc_and2(|_, x| x, &mpc_char('\n'), &mpc_span(&mpc_string("ab")));
+        let x = mpc_parse("<test>", "\nab", &p).unwrap();
+        assert_eq!((x.start.pos, x.start.row, x.start.col), (1, 1, 0));
+        assert_eq!((x.end.pos, x.end.row, x.end.col), (3, 1, 2));
+    }
+
+    #[test]
+    fn line_index() {
+        let mut i = mpc_input_new_string("<test>", "ab\ncdé\r\n\nxyz");
+        let s = mpc_input_state_at(&mut i, 7);
+        assert_eq!((s.row, s.col), (1, 3));
+        let s = mpc_input_state_at(&mut i, 10);
+        assert_eq!((s.row, s.col), (3, 0));
+        assert_eq!(mpc_input_line(&mut i, 1).as_deref(), Some("cdé"));
+        assert_eq!(mpc_input_line(&mut i, 2).as_deref(), Some(""));
+        assert_eq!(mpc_input_line(&mut i, 3).as_deref(), Some("xyz"));
+        assert_eq!(mpc_input_line(&mut i, 4), None);
+
+        let p = mpc_and2(
+            |x, _| x,
+            &mpc_many(mpcf_strfold, &mpc_noneof("!")),
+            &mpc_eoi(),
+        );
+        let e = mpc_parse("<test>", "ab\ncdé!", &p).unwrap_err();
+        assert_eq!(mpc_err_line(&e), Some("cdé!"));
+        assert_eq!((mpc_err_state(&e).row, mpc_err_state(&e).col), (1, 3));
+    }
+
+    #[test]
+    fn pipe_long_line() {
+        let word = mpc_many1(mpcf_strfold, &mpc_alpha());
+        let p = mpc_many(|xs: Vec<String>| xs, &mpc_tok(&word));
+        let input = format!("ab\n{}!", "word ".repeat(10000));
+        let mut i = mpc_input_new_pipe("<test>", trickle(&input, None));
+        assert_eq!(mpc_parse_input(&mut i, &p).unwrap().len(), 10001);
+        assert!(i.buffer.len() <= MPC_INPUT_LINE_MAX as usize);
+
+        let s = mpc_input_state(&mut i);
+        assert_eq!((s.row, s.col), (1, 50000));
+        assert_eq!(mpc_input_line(&mut i, 1), None);
+
+        let mut i = mpc_input_new_pipe("<test>", trickle("ab\ncd !", None));
+        assert_eq!(mpc_parse_input(&mut i, &p).unwrap().len(), 2);
+        let s = mpc_input_state(&mut i);
+        assert_eq!((s.row, s.col), (1, 3));
+        assert_eq!(mpc_input_line(&mut i, 1).as_deref(), Some("cd !"));
+    }
+
+    fn col_at(mode: mpc_col_mode_t, s: &str) -> i32 {
+        let mut i = mpc_input_new_string("<test>", s);
+        mpc_input_col_mode(&mut i, mode);
+        let p = mpc_and2(
+            |_, s: mpc_state_t| s.col,
+            &mpc_many(mpcf_strfold, &mpc_noneof("!")),
+            &mpc_state(),
+        );
+        mpc_parse_input(&mut i, &p).unwrap()
+    }
+
+    #[test]
+    fn column_modes() {
+        use mpc_col_mode_t::*;
+        let s = "a\té😀e\u{301}!";
+        assert_eq!(col_at(mpc_col_chars, s), 6);
+        assert_eq!(col_at(mpc_col_bytes, s), 11);
+        assert_eq!(col_at(mpc_col_utf16, s), 7);
+        assert_eq!(col_at(mpc_col_graphemes, s), 5);
+        assert_eq!(col_at(mpc_col_width(4), s), 8);
+        assert_eq!(col_at(mpc_col_width(8), "x\ty\tz!"), 17);
+
+        let mut i = mpc_input_new_string("<test>", "😀x");
+        mpc_input_col_mode(&mut i, mpc_col_utf16);
+        let e = mpc_parse_input(&mut i, &mpc_string("😀y")).unwrap_err();
+        assert_eq!(mpc_err_state(&e).col, 0);
+        let e = mpc_parse_input(
+            &mut i,
+            &mpc_and(mpcf_strfold, &[&mpc_any(), &mpc_char('y')]),
+        );
+        assert_eq!(mpc_err_state(&e.unwrap_err()).col, 2);
+    }
+
+    #[test]
+    fn nul_input() {
+        let p = mpc_and2(|s, _| s, &mpc_many(mpcf_strfold, &mpc_any()), &mpc_eoi());
+        assert_eq!(mpc_parse("<test>", "a\0b\0", &p).unwrap(), "a\0b\0");
+        let file = std::io::Cursor::new(b"\0\0".to_vec());
+        assert_eq!(mpc_parse_file("<test>", file, &p).unwrap(), "\0\0");
+        let pipe: &[u8] = b"x\0";
+        assert_eq!(mpc_parse_pipe("<test>", pipe, &p).unwrap(), "x\0");
+        assert_eq!(mpc_parse("<test>", "\0", &mpc_char('\0')).unwrap(), "\0");
+
+        let e = mpc_parse("<test>", "ab", &mpc_string("abc")).unwrap_err();
+        assert_eq!(mpc_err_received(&e), Some('a'));
+        let e = mpc_parse("<test>", "", &mpc_char('\0')).unwrap_err();
+        assert_eq!(mpc_err_received(&e), None);
+        assert_eq!(mpc_err_state(&e).term, 1);
+    }
+
+    #[test]
+    fn start_and_end() {
+        let s = mpc_and2(|_, s: mpc_state_t| s.term, &mpc_any(), &mpc_state());
+        assert_eq!(mpc_parse("<test>", "a", &s).unwrap(), 1);
+        assert_eq!(mpc_parse("<test>", "ab", &s).unwrap(), 0);
+
+        let p = mpc_and3(|_, x, _| x, &mpc_soi(), &mpc_string("ab"), &mpc_eoi());
+        assert!(mpc_parse("<test>", "ab", &p).is_ok());
+        assert!(mpc_parse("<test>", "ab\0", &p).is_err());
+        let p = mpc_and2(|_, _| (), &mpc_char('a'), &mpc_soi());
+        assert!(mpc_parse("<test>", "a", &p).is_err());
+    }
+
+    #[test]
+    fn error_rendering() {
+        let p = mpc_or(&[&mpc_char('a'), &mpc_digit(), &mpc_char('(')]);
+        let e = mpc_parse(
+            "in.txt",
+            "ok\nx",
+            &mpc_and2(|_, x| x, &mpc_string("ok\n"), &p),
+        )
+        .unwrap_err();
+        assert_eq!(
+            mpc_err_string(&e),
+            "in.txt:2:1: error: expected one of '(', 'a' or digit at 'x'\\n  |\\n2 | x\\n  | ^\\n"
+                .replace("\\n", "\n")
+        );
+        assert!(mpc_err_string_color(&e).contains("\x1b["));
+
+        let e = mpc_parse("<test>", "abx", &mpc_string("ab\r\ncx")).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "<test>:1:1: error: expected \"ab\\r\\ncx\" at 'a'"
+        );
+        assert_eq!(mpc_err_expected(&e), ["\"ab\r\ncx\""]);
+        let e = mpc_parse("<test>", "", &mpc_oneof("\t\x1b")).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "<test>:1:1: error: expected one of '\\t\\x1b' at end of input"
+        );
+    }
+
+    #[test]
+    fn error_traits() {
+        fn run(s: &str) -> Result<String, Box<dyn std::error::Error>> {
+            Ok(mpc_parse("cfg", s, &mpc_string("key"))?)
+        }
+        assert_eq!(run("key").unwrap(), "key");
+        let e = run("kex").unwrap_err();
+        assert_eq!(e.to_string(), "cfg:1:1: error: expected \"key\" at 'k'");
+
+        let e = mpc_parse("cfg", "kex", &mpc_string("key")).unwrap_err();
+        assert_eq!(e.clone(), e);
+        assert_ne!(e, mpc_parse("cfg", "x", &mpc_string("key")).unwrap_err());
+        assert_eq!(format!("{e:#}"), mpc_err_string(&e).trim_end());
+        assert!(format!("{e:?}").contains("mpc_err_t"));
+
+        let mut out = vec![];
+        mpc_err_print_to(&e, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), mpc_err_string(&e));
+
+        assert_eq!(mpc_err_filename(&e), "cfg");
+        assert_eq!(mpc_err_state(&e).pos, 0);
+        assert_eq!(mpc_err_expected(&e), ["\"key\""]);
+        assert_eq!(mpc_err_received(&e), Some('k'));
+        assert_eq!(mpc_err_failure(&e), None);
+        assert_eq!(mpc_err_line(&e), Some("kex"));
+        let e = mpc_parse("cfg", "", &mpc_fail::<()>("nope")).unwrap_err();
+        assert_eq!(mpc_err_failure(&e), Some("nope"));
+    }
+
+    #[test]
+    fn error_merging() {
+        let p = mpc_or(&[
+            &mpc_char('c'),
+            &mpc_char('a'),
+            &mpc_char('b'),
+            &mpc_char('a'),
+        ]);
+        let e = mpc_parse("<test>", "x", &p).unwrap_err();
+        assert_eq!(mpc_err_expected(&e), ["'a'", "'b'", "'c'"]);
+        assert_eq!(
+            e.to_string(),
+            "<test>:1:1: error: expected one of 'a', 'b' or 'c' at 'x'"
+        );
+
+        let far = mpc_and(mpcf_strfold, &[&mpc_char('a'), &mpc_char('b')]);
+        let p = mpc_or(&[&mpc_char('x'), &far, &mpc_char('y')]);
+        let e = mpc_parse("<test>", "ac", &p).unwrap_err();
+        assert_eq!(mpc_err_state(&e).pos, 1);
+        assert_eq!(mpc_err_expected(&e), ["'b'"]);
+
+        let p = mpc_or(&[&mpc_char('x'), &mpc_fail("bad")]);
+        let e = mpc_parse("<test>", "a", &p).unwrap_err();
+        assert_eq!(mpc_err_failure(&e), Some("bad"));
+    }
+
+    #[test]
+    fn expect_labels() {
+        let ident = mpc_expect(&mpc_many1(mpcf_strfold, &mpc_alpha()), "identifier");
+        let e = mpc_parse("<test>", "1", &ident).unwrap_err();
+        assert_eq!(mpc_err_expected(&e), ["identifier"]);
+
+        let call = mpc_and(mpcf_strfold, &[&ident, &mpc_char('(')]);
+        let p = mpc_expectf(&call, format_args!("{} call", "function"));
+        let e = mpc_parse("<test>", "1", &p).unwrap_err();
+        assert_eq!(mpc_err_expected(&e), ["function call"]);
+        let e = mpc_parse("<test>", "f)", &p).unwrap_err();
+        assert_eq!(mpc_err_expected(&e), ["'('"]);
+        assert_eq!(mpc_err_state(&e).pos, 1);
+    }
+
+    #[test]
+    fn error_recovery() {
+        let value = mpc_many1(mpcf_strfold, &mpc_digit());
+        let value = mpc_recover_until(&value, &mpc_char(';'), |s| format!("<{s}>"));
+        let stmt = mpc_and2(
+            |v, _| v,
+            &value,
+            &mpc_recover_insert(&mpc_char(';'), mpcf_ctor_str),
+        );
+        let p = mpc_many(|xs: Vec<String>| xs, &stmt);
+
+        let (x, es) = mpc_parse_recover("<test>", "1;xx;2;3", &p);
+        assert_eq!(x.unwrap(), ["1", "<xx>", "2", "3"]);
+        let at: Vec<i32> = es.iter().map(|e| mpc_err_state(e).pos).collect();
+        assert_eq!(at, [2, 8]);
+        assert_eq!(mpc_err_expected(&es[1]), ["';'"]);
+        assert_eq!(mpc_parse("<test>", "1;xx;2;3", &p), Err(es[0].clone()));
+        assert!(mpc_parse("<test>", "1;2;", &p).is_ok());
+
+        let p = mpca_recover(
+            &mpca_tag(&mpc_apply(&mpc_digits(), mpcf_str_ast), "num"),
+            &mpc_char(';'),
+        );
+        let (x, es) = mpc_parse_recover("<test>", "ab", &p);
+        assert_eq!(x.unwrap().tag, "error");
+        assert_eq!(es.len(), 1);
+    }
+
+    fn err_in(mode: mpc_col_mode_t, s: &str) -> mpc_err_t {
+        let mut i = mpc_input_new_string("<test>", s);
+        mpc_input_col_mode(&mut i, mode);
+        let p = mpc_and2(|_, x| x, &mpc_char('x'), &mpc_char('y'));
+        mpc_parse_input(&mut i, &p).unwrap_err()
+    }
+
+    #[test]
+    fn error_spans() {
+        use mpc_col_mode_t::*;
+        let e = err_in(mpc_col_utf16, "x😀");
+        assert!(mpc_err_json(&e).contains(
+            "\"start\":{\"offset\":1,\"line\":1,\"column\":2},\"end\":{\"offset\":5,\"line\":1,\"column\":4}"
+        ));
+        let e = err_in(mpc_col_width(8), "x\ty");
+        assert!(mpc_err_json(&e).contains(
+            "\"start\":{\"offset\":1,\"line\":1,\"column\":2},\"end\":{\"offset\":2,\"line\":1,\"column\":9}"
+        ));
+        let e = err_in(mpc_col_chars, "xé");
+        assert!(mpc_err_json(&e).contains("\"end\":{\"offset\":3,\"line\":1,\"column\":3}"));
+
+        let sarif = mpc_err_sarif(&e);
+        assert!(sarif.contains("\"columnKind\":\"unicodeCodePoints\""));
+        assert!(sarif.contains(
+            "\"region\":{\"startLine\":1,\"startColumn\":2,\"endLine\":1,\"endColumn\":3,\"byteOffset\":1,\"byteLength\":2"
+        ));
+        let e = err_in(mpc_col_chars, "x");
+        assert!(mpc_err_json(&e).contains("\"end\":{\"offset\":1,\"line\":1,\"column\":2}"));
+
+        // SARIF columns count characters whatever the input counted.
+        let e = err_in(mpc_col_utf16, "x😀");
+        assert!(mpc_err_sarif(&e).contains(
+            "\"region\":{\"startLine\":1,\"startColumn\":2,\"endLine\":1,\"endColumn\":3,\"byteOffset\":1,\"byteLength\":4"
+        ));
+        let e = err_in(mpc_col_width(8), "x\ty");
+        assert!(mpc_err_sarif(&e).contains("\"startColumn\":2,\"endLine\":1,\"endColumn\":3,"));
+    }
+
+    fn diag(code: Option<&str>, notes: &[&str], help: &[&str]) -> mpc_diag_t {
+        mpc_diag_t {
+            code: code.map(String::from),
+            notes: notes.iter().map(|n| n.to_string()).collect(),
+            help: help.iter().map(|h| h.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn error_diagnostics() {
+        let p: Parser<()> = mpc_fail_diag("no tabs here", diag(Some("E01"), &["tabs vary"], &[]));
+        let e = mpc_parse("<test>", "x", &p).unwrap_err();
+        assert_eq!(mpc_err_code(&e), Some("E01"));
+        assert_eq!(mpc_err_failure(&e), Some("no tabs here"));
+        assert_eq!(
+            mpc_err_string(&e),
+            "<test>:1:1: error[E01]: no tabs here\n  |\n1 | x\n  | ^\n  = note: tabs vary\n"
+        );
+
+        let d = mpc_diag_t {
+            severity: Some(mpc_severity_t::mpc_severity_warning),
+            ..diag(None, &[], &["write a digit"])
+        };
+        let e = mpc_parse(
+            "<test>",
+            "ab",
+            &mpc_expect_diag(&mpc_digit(), "a number", d),
+        )
+        .unwrap_err();
+        assert_eq!(mpc_err_severity(&e), mpc_severity_t::mpc_severity_warning);
+        assert_eq!(mpc_err_help(&e), ["write a digit"]);
+        assert_eq!(
+            mpc_err_string(&e),
+            "<test>:1:1: warning: expected a number at 'a'\n  |\n1 | ab\n  | ^\n  = help: write a digit\n"
+        );
+        assert!(mpc_err_string_color(&e).contains("\x1b[1;33mwarning:\x1b[0m"));
+
+        let small = mpc_check_diag(
+            &mpc_int(),
+            |x| *x < 10,
+            "a small number",
+            diag(Some("E02"), &[], &[]),
+        );
+        assert_eq!(mpc_parse("<test>", "7", &small), Ok(7));
+        let e = mpc_parse("<test>", "12", &small).unwrap_err();
+        assert_eq!(mpc_err_code(&e), Some("E02"));
+        assert_eq!(e.to_string(), "<test>:1:3: error[E02]: a small number");
+
+        // Code is replaced while labels, notes and help accumulate.
+        let mut e = mpc_parse(
+            "<test>",
+            "(1 x",
+            &mpc_and3(
+                |_, _, c| c,
+                &mpc_string("(1"),
+                &mpc_whitespace(),
+                &mpc_char(')'),
+            ),
+        )
+        .unwrap_err();
+        let mut i = mpc_input_new_string("<test>", "(1 x");
+        let span = mpc_span_t {
+            start: mpc_input_state_at(&mut i, 0),
+            end: mpc_input_state_at(&mut i, 1),
+        };
+        let label = mpc_label_t {
+            span,
+            message: "opened here".to_owned(),
+        };
+        mpc_err_annotate(&mut e, diag(Some("E03"), &["first"], &[]));
+        mpc_err_annotate(
+            &mut e,
+            mpc_diag_t {
+                labels: vec![label.clone()],
+                ..diag(Some("E04"), &["second"], &["close it"])
+            },
+        );
+        assert_eq!(mpc_err_code(&e), Some("E04"));
+        assert_eq!(mpc_err_notes(&e), ["first", "second"]);
+        assert_eq!(mpc_err_labels(&e), [label]);
+        assert_eq!(
+            mpc_err_string(&e),
+            "<test>:1:4: error[E04]: expected ')' at 'x'\n  |\n1 | (1 x\n  |    ^\n  | - opened here\n  = note: first\n  = note: second\n  = help: close it\n"
+        );
+        assert!(mpc_err_json(&e).contains("\"code\":\"E04\",\"severity\":\"error\""));
+        assert!(mpc_err_sarif(&e).contains("\"ruleId\":\"E04\""));
+    }
+
+    #[test]
+    fn error_suggestions() {
+        let kw = mpc_or(&[
+            &mpc_string("while"),
+            &mpc_string("return"),
+            &mpc_string("if"),
+        ]);
+        let stmt = mpc_and2(|k, _| k, &kw, &mpc_char(';'));
+        let e = mpc_parse("<test>", "retrun;", &stmt).unwrap_err();
+        assert_eq!(mpc_err_help(&e), ["did you mean `return`?"]);
+        assert!(mpc_err_string(&e).ends_with("  = help: did you mean `return`?\n"));
+        let e = mpc_parse_pipe("<test>", "whiel;".as_bytes(), &stmt).unwrap_err();
+        assert_eq!(mpc_err_help(&e), ["did you mean `while`?"]);
+
+        // Enclosing alternatives failing at the same place are looked at too,
+        // but not for symbols.
+        let p = mpc_or(&[&stmt, &mpc_string("whale")]);
+        let e = mpc_parse("<test>", "whlie", &p).unwrap_err();
+        assert_eq!(mpc_err_help(&e), ["did you mean `while`?"]);
+        let e = mpc_parse("<test>", "whalr", &p).unwrap_err();
+        assert_eq!(mpc_err_help(&e), ["did you mean `whale`?"]);
+        let p = mpc_or(&[
+            &mpc_or(&[&mpc_string("while"), &mpc_string("return")]),
+            &mpc_string("whale"),
+        ]);
+        let e = mpc_parse("<test>", "whalr", &p).unwrap_err();
+        assert_eq!(mpc_err_help(&e), ["did you mean `whale`?"]);
+        let e = mpc_parse("<test>", "+", &stmt).unwrap_err();
+        assert!(mpc_err_help(&e).is_empty());
+        let e = mpc_parse("<test>", "xyzzy;", &stmt).unwrap_err();
+        assert!(mpc_err_help(&e).is_empty());
+
+        let e = mpc_parse("<test>", "ab", &mpc_and2(|_, x| x, &mpc_string("ab"), &kw)).unwrap_err();
+        assert!(mpc_err_help(&e).is_empty());
+        let item = mpc_and2(
+            |k, _| k,
+            &mpc_recover_until(&kw, &mpc_char(';'), |s| s),
+            &mpc_char(';'),
+        );
+        let (_, es) = mpc_parse_recover("<test>", "if;fi;retrun;", &mpc_many(mpcf_strfold, &item));
+        let help: Vec<_> = es.iter().map(|e| mpc_err_help(e).to_vec()).collect();
+        assert_eq!(help, [["did you mean `if`?"], ["did you mean `return`?"]]);
+    }
+
+    fn parens() -> (Parser<String>, Parser<String>) {
+        let e = mpc_new("e");
+        let s = mpc_and3(
+            |a: String, b: String, c: String| a + &b + &c,
+            &mpc_char('('),
+            &e,
+            &mpc_char(')'),
+        );
+        mpc_define(&e, &mpc_or(&[&s, &mpc_char('x')]));
+        (e, s)
+    }
+
+    #[test]
+    fn named_cycles() {
+        let (e, s) = parens();
+        let w = Rc::downgrade(&e.p);
+        drop(s);
+        drop(e);
+        assert!(w.upgrade().is_none());
+
+        // Handles on what a named parser refers to, or on what refers to it,
+        // keep it alive until they go.
+        let (e, s) = parens();
+        let w = Rc::downgrade(&e.p);
+        drop(e);
+        assert_eq!(mpc_parse("<test>", "((x))", &s).unwrap(), "((x))");
+        drop(s);
+        assert!(w.upgrade().is_none());
+
+        let (e, s) = parens();
+        let w = Rc::downgrade(&e.p);
+        drop(s);
+        let m = mpc_maybe(&mpc_and2(|a, _| a, &e, &mpc_char(';')));
+        drop(e);
+        assert_eq!(
+            mpc_parse("<test>", "(x);", &m).unwrap(),
+            Some("(x)".to_owned())
+        );
+        drop(m);
+        assert!(w.upgrade().is_none());
+
+        let a: Parser<String> = mpc_new("a");
+        let b: Parser<String> = mpc_new("b");
+        mpc_define(
+            &a,
+            &mpc_or(&[
+                &mpc_and2(|x: String, y: String| x + &y, &mpc_char('a'), &b),
+                &mpc_char('.'),
+            ]),
+        );
+        mpc_define(
+            &b,
+            &mpc_and2(|x: String, y: String| x + &y, &mpc_char('b'), &a),
+        );
+        let (wa, wb) = (Rc::downgrade(&a.p), Rc::downgrade(&b.p));
+        drop(a);
+        assert_eq!(mpc_parse("<test>", "bab.", &b).unwrap(), "bab.");
+        drop(b);
+        assert!(wa.upgrade().is_none() && wb.upgrade().is_none());
+    }
+
+    #[test]
+    fn named_sharing() {
+        // Dropping handles on a large grammar around one named parser only
+        // looks as far as that parser while it has handles.
+        let e: Parser<String> = mpc_new("e");
+        let seqs: Vec<Parser<String>> = (0..20000)
+            .map(|k| mpc_and2(|a, b| a + &b, &mpc_string(&format!("k{k}")), &e))
+            .collect();
+        let refs: Vec<&Parser<String>> = seqs.iter().collect();
+        mpc_define(&e, &mpc_or(&[&mpc_or(&refs), &mpc_char('x')]));
+        drop(refs);
+        drop(seqs);
+        assert!(matches!(&e.kind, mpc_type_t::NAMED(d, _) if d.borrow().is_some()));
+        let w = Rc::downgrade(&e.p);
+        drop(e);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn lang_left_recursion_errors() {
+        let expr: Parser<mpc_ast_t> = mpc_new("expr");
+        let digit: Parser<mpc_ast_t> = mpc_new("digit");
+        mpca_lang(
+            MPCA::LANG_DEFAULT as i32,
+            "expr : <expr> '+' <digit> | <digit> ; digit : /[0-9]/ ;",
+            &[&expr, &digit],
+        )
+        .unwrap();
+
+        // Grouped to the left, as (1+2)+3.
+        let a = mpc_parse("<test>", "1+2+3", &expr).unwrap();
+        assert_eq!(a.children.len(), 3);
+        assert_eq!(a.children[2].contents, "3");
+        assert_eq!(a.children[0].children.len(), 3);
+        assert_eq!(a.children[0].children[0].contents, "1");
+
+        // The rule calling back into itself adds nothing to what is expected.
+        let sum: Parser<String> = mpc_new("sum");
+        let plus = mpc_and3(
+            |a: String, b, c| a + &b + &c,
+            &sum,
+            &mpc_char('+'),
+            &mpc_digit(),
+        );
+        mpc_define(&sum, &mpc_or(&[&plus, &mpc_digit()]));
+        assert_eq!(mpc_parse("<test>", "1+2+3", &sum).unwrap(), "1+2+3");
+        let e = mpc_parse("<test>", "+", &sum).unwrap_err();
+        assert_eq!(e.to_string(), "<test>:1:1: error: expected digit at '+'");
+
+        let a: Parser<mpc_ast_t> = mpc_new("a");
+        let b: Parser<mpc_ast_t> = mpc_new("b");
+        mpca_lang(0, "a : <b> 'x' | 'y' ; b : <a> 'z' | 'w' ;", &[&a, &b]).unwrap();
+        assert!(mpc_parse("<test>", "yzxzx", &a).is_ok());
+        assert!(mpc_parse("<test>", "wx", &a).is_ok());
+        let e = mpc_parse("<test>", "q", &a).unwrap_err();
+        assert_eq!(mpc_err_expected(&e), ["a"]);
+
+        let c: Parser<String> = mpc_new("c");
+        mpc_define(&c, &mpc_and2(|a: String, b| a + &b, &c, &mpc_char('x')));
+        let e = mpc_parse("<test>", "x", &c).unwrap_err();
+        assert_eq!(
+            mpc_err_failure(&e),
+            Some("Left recursion in c has no base case!")
+        );
+        mpc_cleanup(&[&expr, &digit, &a, &b]);
+    }
+
+    fn parse_re(re: &str, mode: i32, s: &str) -> Result<String, String> {
+        let p = mpc_and2(|x, _| x, &mpc_re_mode(re, mode), &mpc_eoi());
+        mpc_parse("<test>", s, &p).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn regex_matches() {
+        let d = MPC::RE_DEFAULT as i32;
+        assert_eq!(parse_re("ab|cd", d, "cd").as_deref(), Ok("cd"));
+        assert_eq!(parse_re("[a-c]+x?", d, "abca").as_deref(), Ok("abca"));
+        assert_eq!(parse_re("[^0-9]*", d, "hi").as_deref(), Ok("hi"));
+        assert_eq!(parse_re("\\d{3}", d, "123").as_deref(), Ok("123"));
+        assert_eq!(parse_re("^(ab)*$", d, "abab").as_deref(), Ok("abab"));
+        assert_eq!(parse_re("\\w+\\s\\w+", d, "a b").as_deref(), Ok("a b"));
+        assert_eq!(parse_re("[\\]x]+", d, "]x]").as_deref(), Ok("]x]"));
+        assert!(parse_re("a.c", d, "a\nc").is_err());
+        assert_eq!(
+            parse_re("a.c", MPC::RE_S as i32, "a\nc").as_deref(),
+            Ok("a\nc")
+        );
+        assert!(parse_re("a$\nb", d, "a\nb").is_err());
+        assert_eq!(
+            parse_re("a$\n^b", MPC::RE_M as i32, "a\nb").as_deref(),
+            Ok("a\nb")
+        );
+    }
+
+    #[test]
+    fn regex_errors() {
+        let e = mpc_parse("<test>", "x", &mpc_re("[0-9]+")).unwrap_err();
+        assert_eq!(mpc_err_expected(&e), ["/[0-9]+/"]);
+        let e = mpc_parse("<test>", "x", &mpc_re("a)")).unwrap_err();
+        assert_eq!(
+            mpc_err_failure(&e),
+            Some("Invalid Regex /a)/: expected end of input at ')'")
+        );
+    }
+
+    fn eval(a: &mpc_ast_t) -> i64 {
+        match a.children.as_slice() {
+            [] => a.contents.parse().unwrap(),
+            [x] => eval(x),
+            [l, x, _] if l.contents == "(" => eval(x),
+            [l, op, r] => match op.contents.as_str() {
+                "+" => eval(l) + eval(r),
+                "-" => eval(l) - eval(r),
+                _ => eval(l) * eval(r),
+            },
+            _ => panic!("unexpected node {}", a.tag),
+        }
+    }
+
+    #[test]
+    fn lang_left_recursion() {
+        let expr: Parser<mpc_ast_t> = mpc_new("expr");
+        let term: Parser<mpc_ast_t> = mpc_new("term");
+        let num: Parser<mpc_ast_t> = mpc_new("num");
+        let prog: Parser<mpc_ast_t> = mpc_new("prog");
+        mpca_lang(
+            MPCA::LANG_DEFAULT as i32,
+            r#"
+            expr : <expr> '+' <term> | <expr> '-' <term> | <term> ;
+            term : <term> '*' <num> | <num> ;
+            num "number" : /[0-9]+/ | '(' <expr> ')' ;
+            prog : /^/ <expr> /$/ ;
+            "#,
+            &[&expr, &term, &num, &prog],
+        )
+        .unwrap();
+
+        let run = |s| eval(&mpc_parse("<test>", s, &prog).unwrap());
+        assert_eq!(run("1 + 2 * 3 - 4"), 3);
+        assert_eq!(run("10 - 3 - 2"), 5);
+        assert_eq!(run("2 * (3 + 4) * 2"), 28);
+        mpc_cleanup(&[&expr, &term, &num, &prog]);
+    }
+
+    #[test]
+    fn lang_errors() {
+        let x: Parser<mpc_ast_t> = mpc_new("x");
+        let e = mpca_lang(0, "y : 'a' ;", &[&x]).unwrap_err();
+        assert_eq!(mpc_err_failure(&e), Some("Unknown Parser 'y'!"));
+        assert!(mpca_lang(0, "x : 'a' ", &[&x]).is_err());
+
+        let g = mpca_grammer(0, "<1>", &[&x]);
+        let e = mpc_parse("<test>", "a", &g).unwrap_err();
+        assert_eq!(
+            mpc_err_failure(&e),
+            Some("No Parser in position 1! Only supplied 1 Parsers!")
+        );
+    }
+
+    #[test]
+    fn lang_flags() {
+        let w: Parser<mpc_ast_t> = mpc_new("w");
+        let flags = MPCA::LANG_PREDICTIVE as i32 | MPCA::LANG_WHITESPACE_SENSITIVE as i32;
+        mpca_lang(flags, "w : \"ab\" /c+/ ;", &[&w]).unwrap();
+        assert!(mpc_parse("<test>", "abccc", &w).is_ok());
+        assert!(mpc_parse("<test>", "ab c", &w).is_err());
+
+        let pipe = std::io::Cursor::new(b"w : 'x'+ ;".to_vec());
+        mpca_lang_pipe(0, pipe, &[&w]).unwrap();
+        assert!(mpc_parse("<test>", "x x", &w).is_ok());
+
+        let expr: Parser<mpc_ast_t> = mpc_new("expr");
+        let digit: Parser<mpc_ast_t> = mpc_new("digit");
+        mpca_lang(
+            MPCA::LANG_PREDICTIVE as i32,
+            "expr : <expr> '+' <digit> | <digit> ; digit : /[0-9]/ ;",
+            &[&expr, &digit],
+        )
+        .unwrap();
+        assert_eq!(eval(&mpc_parse("<test>", "1+2+3", &expr).unwrap()), 6);
+        mpc_cleanup(&[&expr, &digit]);
+    }
 }